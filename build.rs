@@ -0,0 +1,22 @@
+fn main() {
+    #[cfg(feature = "capi")]
+    generate_header();
+}
+
+/// Generates the C header of `src/capi.rs` in `OUT_DIR`, tests/capi.rs checks that
+/// include/raw_sync.h is up to date with it
+#[cfg(feature = "capi")]
+fn generate_header() {
+    let root = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_file(root.join("cbindgen.toml")).unwrap();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(root.join("src").join("capi.rs"))
+        .generate()
+        .expect("failed to generate the C header")
+        .write_to_file(out.join("raw_sync.h"));
+}
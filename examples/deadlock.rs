@@ -0,0 +1,65 @@
+use std::thread;
+use std::time;
+
+use env_logger::Env;
+use log::*;
+use raw_sync::locks::*;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// The `u64` buffer below keeps every primitive aligned
+const _: () = assert!(LockRegistry::ALIGN <= 8 && Mutex::ALIGN <= 8);
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    // The registry followed by two mutexes, each aligned as `new()` requires
+    let lock_a = LockRegistry::SIZE.next_multiple_of(Mutex::ALIGN);
+    let lock_b = (lock_a + Mutex::SIZE).next_multiple_of(Mutex::ALIGN);
+    let mut mem = vec![0u64; (lock_b + Mutex::SIZE).div_ceil(8)];
+
+    let mem_ptr = mem.as_mut_ptr() as usize;
+    let (registry, _) = unsafe { LockRegistry::new(mem_ptr as _)? };
+    let lock_a_ptr = mem_ptr + lock_a;
+    let (_, _) = unsafe { Mutex::new(lock_a_ptr as _, std::ptr::null_mut())? };
+    let lock_b_ptr = mem_ptr + lock_b;
+    let (_, _) = unsafe { Mutex::new(lock_b_ptr as _, std::ptr::null_mut())? };
+
+    let child = thread::spawn(move || {
+        let (registry, _) = unsafe { LockRegistry::from_existing(mem_ptr as _).unwrap() };
+        let (a, _) =
+            unsafe { Mutex::from_existing(lock_a_ptr as _, std::ptr::null_mut()).unwrap() };
+        let (b, _) =
+            unsafe { Mutex::from_existing(lock_b_ptr as _, std::ptr::null_mut()).unwrap() };
+        let a = registry.track(1, a);
+        let b = registry.track(2, b);
+
+        let _guard_b = b.lock().unwrap();
+        info!("\t[child] holding B, waiting for A");
+        thread::sleep(time::Duration::from_millis(500));
+        match a.lock() {
+            Ok(_) => info!("\t[child] acquired A"),
+            Err(e) => info!("\t[child] {}", e),
+        };
+    });
+
+    let (a, _) = unsafe { Mutex::from_existing(lock_a_ptr as _, std::ptr::null_mut())? };
+    let (b, _) = unsafe { Mutex::from_existing(lock_b_ptr as _, std::ptr::null_mut())? };
+    let a = registry.track(1, a);
+    let b = registry.track(2, b);
+
+    {
+        let _guard_a = a.lock()?;
+        info!("[parent] holding A, waiting for B");
+        thread::sleep(time::Duration::from_millis(250));
+        match b.lock() {
+            Ok(_) => info!("[parent] acquired B"),
+            Err(e) => info!("[parent] {}", e),
+        };
+        for d in registry.find_deadlocks()? {
+            info!("[parent] deadlock : {}", d);
+        }
+    }
+
+    let _ = child.join();
+    Ok(())
+}
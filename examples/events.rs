@@ -7,9 +7,20 @@ use raw_sync::{events::*, Timeout};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// Stands in for shared memory, large enough and aligned for every event used below
+const MEM_SIZE: usize = if Event::SIZE > BusyEvent::SIZE {
+    Event::SIZE
+} else {
+    BusyEvent::SIZE
+};
+#[repr(C, align(8))]
+struct Mem([u8; MEM_SIZE]);
+const _: () = assert!(Event::ALIGN <= 8 && BusyEvent::ALIGN <= 8);
+
 fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-    let mut mem = [0u8; 64];
+    let mut mem = Mem([0; MEM_SIZE]);
+    let mem = &mut mem.0;
 
     // Regular event
     event_example(mem.as_mut_ptr(), true)?;
@@ -7,6 +7,22 @@ use raw_sync::{locks::*, Timeout};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// Stands in for shared memory, large enough and aligned for every lock used below
+#[cfg(not(windows))]
+const MEM_SIZE: usize = if Mutex::SIZE > RwLock::SIZE {
+    Mutex::SIZE
+} else {
+    RwLock::SIZE
+};
+#[cfg(windows)]
+const MEM_SIZE: usize = Mutex::SIZE;
+#[repr(C, align(8))]
+struct Mem([u8; MEM_SIZE]);
+#[cfg(not(windows))]
+const _: () = assert!(Mutex::ALIGN <= 8 && RwLock::ALIGN <= 8);
+#[cfg(windows)]
+const _: () = assert!(Mutex::ALIGN <= 8);
+
 fn test_timeout(id: u8, lock: &dyn LockImpl) {
     info!("[{}] Waiting for lock for 1 second", id);
     let guard = lock.try_lock(Timeout::Val(time::Duration::from_secs(1)));
@@ -49,7 +65,8 @@ fn increment_val(id: u8, lock: Box<dyn LockImpl>) {
 
 fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-    let mut mem = [0u8; 64];
+    let mut mem = Mem([0; MEM_SIZE]);
+    let mem = &mut mem.0;
 
     test_mutex(mem.as_mut_ptr())?;
 
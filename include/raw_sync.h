@@ -0,0 +1,127 @@
+/*
+ * C bindings of raw_sync, built with the `capi` feature.
+ * Generated from src/capi.rs by build.rs, do not edit.
+ *
+ * Functions returning an int return 0 on success and -1 on failure, in which case
+ * raw_sync_last_error() describes the error.
+ */
+
+#ifndef RAW_SYNC_H
+#define RAW_SYNC_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ Timeout value waiting forever
+ */
+#define RAW_SYNC_INFINITE UINT64_MAX
+
+/*
+ Opaque handle on an `events::Event`
+ */
+typedef struct RawSyncEvent RawSyncEvent;
+
+/*
+ Opaque handle on a `locks::Mutex`
+ */
+typedef struct RawSyncMutex RawSyncMutex;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Returns the message of the last error raised on the calling thread or NULL. The string
+ stays valid until the next failing call on the same thread.
+ */
+const char *raw_sync_last_error(void);
+
+/*
+ Size required for a mutex at `addr`, or its size at an address aligned to
+ `raw_sync_mutex_align()` when `addr` is NULL
+ */
+size_t raw_sync_mutex_size_of(void *addr);
+
+/*
+ Alignment required for the memory given to `raw_sync_mutex_new()` and `raw_sync_mutex_from_existing()`
+ */
+size_t raw_sync_mutex_align(void);
+
+/*
+ Initializes a new mutex in `mem` protecting `data`
+ */
+int raw_sync_mutex_new(void *mem, void *data, struct RawSyncMutex **out, size_t *used);
+
+/*
+ Opens the mutex already initialized in `mem`
+ */
+int raw_sync_mutex_from_existing(void *mem, void *data, struct RawSyncMutex **out, size_t *used);
+
+/*
+ Releases the handle, the mutex stays usable by other handles
+ */
+void raw_sync_mutex_free(struct RawSyncMutex *mutex);
+
+/*
+ Returns the data pointer protected by the mutex
+ */
+void *raw_sync_mutex_data(const struct RawSyncMutex *mutex);
+
+/*
+ Locks the mutex, waiting as long as needed
+ */
+int raw_sync_mutex_lock(const struct RawSyncMutex *mutex);
+
+/*
+ Locks the mutex, giving up after `timeout_ms` milliseconds
+ */
+int raw_sync_mutex_timed_lock(const struct RawSyncMutex *mutex, uint64_t timeout_ms);
+
+/*
+ Unlocks a mutex locked by the calling thread
+ */
+int raw_sync_mutex_unlock(const struct RawSyncMutex *mutex);
+
+/*
+ Size required for an event at `addr`, or its size at an address aligned to
+ `raw_sync_event_align()` when `addr` is NULL
+ */
+size_t raw_sync_event_size_of(void *addr);
+
+/*
+ Alignment required for the memory given to `raw_sync_event_new()` and `raw_sync_event_from_existing()`
+ */
+size_t raw_sync_event_align(void);
+
+/*
+ Initializes a new event in `mem`, cleared
+ */
+int raw_sync_event_new(void *mem, bool auto_reset, struct RawSyncEvent **out, size_t *used);
+
+/*
+ Opens the event already initialized in `mem`
+ */
+int raw_sync_event_from_existing(void *mem, struct RawSyncEvent **out, size_t *used);
+
+/*
+ Releases the handle, the event stays usable by other handles
+ */
+void raw_sync_event_free(struct RawSyncEvent *event);
+
+/*
+ Waits for the event to be signaled, giving up after `timeout_ms` milliseconds
+ */
+int raw_sync_event_wait(const struct RawSyncEvent *event, uint64_t timeout_ms);
+
+/*
+ Signals the event when `signaled` is true and clears it otherwise
+ */
+int raw_sync_event_set(const struct RawSyncEvent *event, bool signaled);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RAW_SYNC_H */
@@ -0,0 +1,236 @@
+//! C bindings for `locks::Mutex` and `events::Event`, declared in `include/raw_sync.h`.
+//!
+//! Objects are handed out as opaque pointers that must be released with the matching
+//! `_free()` function. Functions returning an `int` return 0 on success and -1 on failure,
+//! in which case `raw_sync_last_error()` describes the error.
+//!
+//! # Safety
+//! Every pointer passed to these functions must be valid : `mem` must point to memory
+//! usable by the primitive and handles must come from the matching `_new()` or
+//! `_from_existing()` function and not be used after `_free()`.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_void, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr::null;
+use std::time::Duration;
+
+use crate::events::{Event, EventImpl, EventInit, EventState};
+use crate::locks::{LockImpl, LockInit, Mutex};
+use crate::{Result, Timeout};
+
+/// Timeout value waiting forever
+pub const RAW_SYNC_INFINITE: u64 = u64::MAX;
+
+/// Opaque handle on a `locks::Mutex`
+pub struct RawSyncMutex(Box<dyn LockImpl>);
+/// Opaque handle on an `events::Event`
+pub struct RawSyncEvent(Box<dyn EventImpl>);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Stores the error of `res` for `raw_sync_last_error()` and converts it to a return code
+fn status<T>(res: Result<T>) -> std::result::Result<T, c_int> {
+    res.map_err(|e| {
+        let msg = CString::new(e.to_string().replace('\0', " ")).unwrap_or_default();
+        LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg));
+        -1
+    })
+}
+
+fn timeout_from_ms(timeout_ms: u64) -> Timeout {
+    match timeout_ms {
+        RAW_SYNC_INFINITE => Timeout::Infinite,
+        ms => Timeout::Val(Duration::from_millis(ms)),
+    }
+}
+
+fn addr(mem: *mut c_void) -> Option<*mut u8> {
+    if mem.is_null() {
+        None
+    } else {
+        Some(mem as *mut u8)
+    }
+}
+
+/// Writes a newly created object to the out parameters
+unsafe fn output<T>(res: Result<(T, usize)>, out: *mut *mut T, used: *mut usize) -> c_int {
+    match status(res) {
+        Ok((obj, used_bytes)) => {
+            *out = Box::into_raw(Box::new(obj));
+            if !used.is_null() {
+                *used = used_bytes;
+            }
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+/// Returns the message of the last error raised on the calling thread or NULL. The string
+/// stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn raw_sync_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => null(),
+    })
+}
+
+/// Size required for a mutex at `addr`, or its size at an address aligned to
+/// `raw_sync_mutex_align()` when `addr` is NULL
+#[no_mangle]
+pub extern "C" fn raw_sync_mutex_size_of(addr: *mut c_void) -> usize {
+    Mutex::size_of(self::addr(addr))
+}
+
+/// Alignment required for the memory given to `raw_sync_mutex_new()` and `raw_sync_mutex_from_existing()`
+#[no_mangle]
+pub extern "C" fn raw_sync_mutex_align() -> usize {
+    Mutex::ALIGN
+}
+
+/// Initializes a new mutex in `mem` protecting `data`
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_mutex_new(
+    mem: *mut c_void,
+    data: *mut c_void,
+    out: *mut *mut RawSyncMutex,
+    used: *mut usize,
+) -> c_int {
+    let res = Mutex::new(mem as _, data as _).map(|(l, u)| (RawSyncMutex(l), u));
+    output(res, out, used)
+}
+
+/// Opens the mutex already initialized in `mem`
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_mutex_from_existing(
+    mem: *mut c_void,
+    data: *mut c_void,
+    out: *mut *mut RawSyncMutex,
+    used: *mut usize,
+) -> c_int {
+    let res = Mutex::from_existing(mem as _, data as _).map(|(l, u)| (RawSyncMutex(l), u));
+    output(res, out, used)
+}
+
+/// Releases the handle, the mutex stays usable by other handles
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_mutex_free(mutex: *mut RawSyncMutex) {
+    if !mutex.is_null() {
+        drop(Box::from_raw(mutex));
+    }
+}
+
+/// Returns the data pointer protected by the mutex
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_mutex_data(mutex: *const RawSyncMutex) -> *mut c_void {
+    *(*mutex).0.get_inner() as _
+}
+
+/// Locks the mutex, waiting as long as needed
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_mutex_lock(mutex: *const RawSyncMutex) -> c_int {
+    match status((*mutex).0.lock()) {
+        Ok(guard) => {
+            // Released by raw_sync_mutex_unlock()
+            std::mem::forget(guard);
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+/// Locks the mutex, giving up after `timeout_ms` milliseconds
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_mutex_timed_lock(
+    mutex: *const RawSyncMutex,
+    timeout_ms: u64,
+) -> c_int {
+    match status((*mutex).0.try_lock(timeout_from_ms(timeout_ms))) {
+        Ok(guard) => {
+            std::mem::forget(guard);
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+/// Unlocks a mutex locked by the calling thread
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_mutex_unlock(mutex: *const RawSyncMutex) -> c_int {
+    match status((*mutex).0.release()) {
+        Ok(()) => 0,
+        Err(code) => code,
+    }
+}
+
+/// Size required for an event at `addr`, or its size at an address aligned to
+/// `raw_sync_event_align()` when `addr` is NULL
+#[no_mangle]
+pub extern "C" fn raw_sync_event_size_of(addr: *mut c_void) -> usize {
+    Event::size_of(self::addr(addr))
+}
+
+/// Alignment required for the memory given to `raw_sync_event_new()` and `raw_sync_event_from_existing()`
+#[no_mangle]
+pub extern "C" fn raw_sync_event_align() -> usize {
+    Event::ALIGN
+}
+
+/// Initializes a new event in `mem`, cleared
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_event_new(
+    mem: *mut c_void,
+    auto_reset: bool,
+    out: *mut *mut RawSyncEvent,
+    used: *mut usize,
+) -> c_int {
+    let res = Event::new(mem as _, auto_reset).map(|(e, u)| (RawSyncEvent(e), u));
+    output(res, out, used)
+}
+
+/// Opens the event already initialized in `mem`
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_event_from_existing(
+    mem: *mut c_void,
+    out: *mut *mut RawSyncEvent,
+    used: *mut usize,
+) -> c_int {
+    let res = Event::from_existing(mem as _).map(|(e, u)| (RawSyncEvent(e), u));
+    output(res, out, used)
+}
+
+/// Releases the handle, the event stays usable by other handles
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_event_free(event: *mut RawSyncEvent) {
+    if !event.is_null() {
+        drop(Box::from_raw(event));
+    }
+}
+
+/// Waits for the event to be signaled, giving up after `timeout_ms` milliseconds
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_event_wait(event: *const RawSyncEvent, timeout_ms: u64) -> c_int {
+    match status((*event).0.wait(timeout_from_ms(timeout_ms))) {
+        Ok(()) => 0,
+        Err(code) => code,
+    }
+}
+
+/// Signals the event when `signaled` is true and clears it otherwise
+#[no_mangle]
+pub unsafe extern "C" fn raw_sync_event_set(event: *const RawSyncEvent, signaled: bool) -> c_int {
+    let state = if signaled {
+        EventState::Signaled
+    } else {
+        EventState::Clear
+    };
+    match status((*event).0.set(state)) {
+        Ok(()) => 0,
+        Err(code) => code,
+    }
+}
@@ -0,0 +1,4 @@
+/// Bounded multi-producer, multi-consumer queue of fixed size messages
+pub mod mpmc;
+/// Single producer, single consumer byte channel
+pub mod spsc;
@@ -0,0 +1,244 @@
+//! A bounded queue of fixed size slots that any number of producers and consumers,
+//! in any number of processes, can use at the same time.
+//!
+//! The queue state is protected by a `Mutex`. Producers blocked on a full queue wait on a
+//! manual reset "not full" event and consumers blocked on an empty queue wait on a manual
+//! reset "not empty" event. Both events are only modified while holding the mutex so their
+//! state always matches the queue's.
+//!
+//! # Fairness
+//! Messages are delivered in FIFO order. Blocked producers and consumers are not : every
+//! waiter wakes up when its event is signaled and they race for the mutex, so under sustained
+//! contention a given producer or consumer can be starved by the others.
+
+use std::mem::{align_of, size_of};
+use std::ptr::null_mut;
+
+use crate::events::{Event, EventImpl, EventInit, EventState};
+use crate::locks::{LockImpl, LockInit, Mutex};
+use crate::{check_align, max_align, Deadline, Result, Timeout};
+
+/// Size of the length written at the start of every slot
+const LEN_SIZE: usize = size_of::<u32>();
+
+#[repr(C)]
+struct Header {
+    capacity: u32,
+    slot_size: u32,
+    /// Index of the oldest message
+    head: u32,
+    /// Number of messages in the queue
+    len: u32,
+}
+
+/// Offsets of the queue's parts
+const MUTEX: usize = size_of::<Header>().next_multiple_of(Mutex::ALIGN);
+const NOT_EMPTY: usize = (MUTEX + Mutex::SIZE).next_multiple_of(Event::ALIGN);
+const NOT_FULL: usize = (NOT_EMPTY + Event::SIZE).next_multiple_of(Event::ALIGN);
+const SLOTS: usize = NOT_FULL + Event::SIZE;
+
+/// Bounded multi-producer, multi-consumer queue in shared memory
+pub struct Queue {
+    header: *mut Header,
+    mutex: Box<dyn LockImpl>,
+    not_empty: Box<dyn EventImpl>,
+    not_full: Box<dyn EventImpl>,
+    slots: *mut u8,
+}
+
+impl Queue {
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(max_align(align_of::<Header>(), Mutex::ALIGN), Event::ALIGN);
+
+    /// Size of a queue of `capacity` messages of at most `slot_size` bytes at an address aligned to `ALIGN`
+    pub const fn size_for(capacity: usize, slot_size: usize) -> usize {
+        SLOTS + capacity * (LEN_SIZE + slot_size)
+    }
+
+    /// Size required for a queue of `capacity` messages of at most `slot_size` bytes
+    pub fn size_of(addr: Option<*mut u8>, capacity: usize, slot_size: usize) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::size_for(capacity, slot_size)
+    }
+
+    /// Initializes a new queue in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8, capacity: usize, slot_size: usize) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "mpmc queue")?;
+        if capacity == 0
+            || capacity > u32::MAX as usize
+            || slot_size > u32::MAX as usize
+            || capacity.checked_mul(LEN_SIZE + slot_size).is_none()
+        {
+            return Err(From::from(format!(
+                "Invalid mpmc queue dimensions : {} slots of {} bytes",
+                capacity, slot_size
+            )));
+        }
+        let header = mem as *mut Header;
+        header.write(Header {
+            capacity: capacity as u32,
+            slot_size: slot_size as u32,
+            head: 0,
+            len: 0,
+        });
+        let (mutex, _) = Mutex::new(mem.add(MUTEX), null_mut())?;
+        let (not_empty, _) = Event::new(mem.add(NOT_EMPTY), false)?;
+        let (not_full, _) = Event::new(mem.add(NOT_FULL), false)?;
+        not_full.set(EventState::Signaled)?;
+
+        let obj = Self {
+            header,
+            mutex,
+            not_empty,
+            not_full,
+            slots: mem.add(SLOTS),
+        };
+        Ok((obj, Self::size_for(capacity, slot_size)))
+    }
+
+    /// Re-uses a queue from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "mpmc queue")?;
+        let header = mem as *mut Header;
+        let (capacity, slot_size) = ((*header).capacity, (*header).slot_size);
+        if capacity == 0 || (*header).head >= capacity || (*header).len > capacity {
+            return Err(From::from("Existing mpmc queue is corrupted"));
+        }
+        let (mutex, _) = Mutex::from_existing(mem.add(MUTEX), null_mut())?;
+        let (not_empty, _) = Event::from_existing(mem.add(NOT_EMPTY))?;
+        let (not_full, _) = Event::from_existing(mem.add(NOT_FULL))?;
+
+        let obj = Self {
+            header,
+            mutex,
+            not_empty,
+            not_full,
+            slots: mem.add(SLOTS),
+        };
+        Ok((obj, Self::size_for(capacity as usize, slot_size as usize)))
+    }
+
+    /// Maximum number of messages the queue can hold
+    pub fn capacity(&self) -> usize {
+        unsafe { (*self.header).capacity as usize }
+    }
+
+    /// Maximum size of a single message
+    pub fn slot_size(&self) -> usize {
+        unsafe { (*self.header).slot_size as usize }
+    }
+
+    /// Number of messages currently in the queue
+    pub fn len(&self) -> Result<usize> {
+        let guard = self.mutex.lock()?;
+        let len = unsafe { (*self.header).len as usize };
+        drop(guard);
+        Ok(len)
+    }
+
+    /// Returns true if the queue holds no messages
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    fn slot(&self, idx: u32) -> *mut u8 {
+        unsafe { self.slots.add(idx as usize * (LEN_SIZE + self.slot_size())) }
+    }
+
+    /// Adds a message to the back of the queue, waiting for a free slot if it is full
+    pub fn push(&self, msg: &[u8], timeout: Timeout) -> Result<()> {
+        if msg.len() > self.slot_size() {
+            return Err(From::from(format!(
+                "Message of {} bytes does not fit in slots of {} bytes",
+                msg.len(),
+                self.slot_size()
+            )));
+        }
+
+        let deadline = Deadline::new(timeout);
+        loop {
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(From::from("Timed out waiting for space in queue")),
+            };
+            let guard = self.mutex.try_lock(remaining)?;
+            let header = unsafe { &mut *self.header };
+            if header.len < header.capacity {
+                // head + len can exceed u32::MAX once the capacity is above 2^31
+                let tail = (header.head as u64 + header.len as u64) % header.capacity as u64;
+                let slot = self.slot(tail as u32);
+                unsafe {
+                    (slot as *mut u32).write_unaligned(msg.len() as u32);
+                    std::ptr::copy_nonoverlapping(msg.as_ptr(), slot.add(LEN_SIZE), msg.len());
+                }
+                header.len += 1;
+                trace!("mpmc push({:p}) len = {}", self.header, header.len);
+
+                let mut res = self.not_empty.set(EventState::Signaled);
+                if res.is_ok() && header.len == header.capacity {
+                    res = self.not_full.set(EventState::Clear);
+                }
+                drop(guard);
+                return res;
+            }
+            drop(guard);
+
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(From::from("Timed out waiting for space in queue")),
+            };
+            self.not_full.wait(remaining)?;
+        }
+    }
+
+    /// Removes the message at the front of the queue, waiting for one if it is empty.
+    /// Returns the size of the message copied into `buf`, which must be able to hold `slot_size()` bytes.
+    pub fn pop(&self, buf: &mut [u8], timeout: Timeout) -> Result<usize> {
+        let deadline = Deadline::new(timeout);
+        loop {
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(From::from("Timed out waiting for data in queue")),
+            };
+            let guard = self.mutex.try_lock(remaining)?;
+            let header = unsafe { &mut *self.header };
+            if header.len > 0 {
+                let slot = self.slot(header.head);
+                let len = unsafe { (slot as *const u32).read_unaligned() } as usize;
+                if len > buf.len() {
+                    drop(guard);
+                    return Err(From::from(format!(
+                        "Buffer of {} bytes is too small for message of {} bytes",
+                        buf.len(),
+                        len
+                    )));
+                }
+                unsafe { std::ptr::copy_nonoverlapping(slot.add(LEN_SIZE), buf.as_mut_ptr(), len) };
+                header.head = (header.head + 1) % header.capacity;
+                header.len -= 1;
+                trace!("mpmc pop({:p}) len = {}", self.header, header.len);
+
+                let mut res = self.not_full.set(EventState::Signaled);
+                if res.is_ok() && header.len == 0 {
+                    res = self.not_empty.set(EventState::Clear);
+                }
+                drop(guard);
+                return res.map(|_| len);
+            }
+            drop(guard);
+
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(From::from("Timed out waiting for data in queue")),
+            };
+            self.not_empty.wait(remaining)?;
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! A ring buffer of length prefixed messages with one event signaling "data available"
+//! and another signaling "space available". Only one `Sender` and one `Receiver` may
+//! use a channel at any time, they can live in different processes.
+
+use std::mem::{align_of, size_of};
+
+use crate::events::{Event, EventImpl, EventInit, EventState};
+use crate::sync::{AtomicU32, Ordering};
+use crate::{check_align, max_align, Deadline, Result, Timeout};
+
+/// Size of the length prefix written before every message
+const LEN_SIZE: usize = size_of::<u32>();
+
+#[repr(C)]
+struct Header {
+    /// Size of the ring buffer in bytes
+    capacity: u32,
+    /// Offset of the next message to read, only written by the receiver
+    head: AtomicU32,
+    /// Offset where the next message will be written, only written by the sender
+    tail: AtomicU32,
+    /// Number of bytes currently in the ring buffer
+    used: AtomicU32,
+}
+
+struct Channel {
+    header: *mut Header,
+    data_available: Box<dyn EventImpl>,
+    space_available: Box<dyn EventImpl>,
+    ring: *mut u8,
+}
+
+/// Alignment required for the memory of a channel
+const ALIGN: usize = max_align(align_of::<Header>(), Event::ALIGN);
+/// Offsets of the two events and of the ring buffer
+const DATA_AVAILABLE: usize = size_of::<Header>().next_multiple_of(Event::ALIGN);
+const SPACE_AVAILABLE: usize = (DATA_AVAILABLE + Event::SIZE).next_multiple_of(Event::ALIGN);
+const RING: usize = SPACE_AVAILABLE + Event::SIZE;
+
+fn channel_size_of(addr: Option<*mut u8>, capacity: usize) -> usize {
+    let padding = match addr {
+        Some(mem) => mem.align_offset(ALIGN),
+        None => 0,
+    };
+    padding + RING + capacity
+}
+
+impl Channel {
+    unsafe fn new(mem: *mut u8, capacity: usize) -> Result<(Self, usize)> {
+        check_align(mem, ALIGN, "spsc channel")?;
+        if capacity <= LEN_SIZE || capacity > u32::MAX as usize {
+            return Err(From::from(format!(
+                "Invalid spsc channel capacity : {}",
+                capacity
+            )));
+        }
+        let header = mem as *mut Header;
+        header.write(Header {
+            capacity: capacity as u32,
+            head: AtomicU32::new(0),
+            tail: AtomicU32::new(0),
+            used: AtomicU32::new(0),
+        });
+        let (data_available, _) = Event::new(mem.add(DATA_AVAILABLE), true)?;
+        let (space_available, _) = Event::new(mem.add(SPACE_AVAILABLE), true)?;
+
+        let obj = Self {
+            header,
+            data_available,
+            space_available,
+            ring: mem.add(RING),
+        };
+        Ok((obj, RING + capacity))
+    }
+
+    unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, ALIGN, "spsc channel")?;
+        let header = mem as *mut Header;
+        let capacity = (*header).capacity as usize;
+        if capacity <= LEN_SIZE
+            || (*header).used.load(Ordering::Relaxed) as usize > capacity
+            || (*header).head.load(Ordering::Relaxed) as usize >= capacity
+            || (*header).tail.load(Ordering::Relaxed) as usize >= capacity
+        {
+            return Err(From::from("Existing spsc channel is corrupted"));
+        }
+        let (data_available, _) = Event::from_existing(mem.add(DATA_AVAILABLE))?;
+        let (space_available, _) = Event::from_existing(mem.add(SPACE_AVAILABLE))?;
+
+        let obj = Self {
+            header,
+            data_available,
+            space_available,
+            ring: mem.add(RING),
+        };
+        Ok((obj, RING + capacity))
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    /// Copies `src` into the ring at `offset`, wrapping around the end
+    fn write_at(&self, offset: usize, src: &[u8]) -> usize {
+        let capacity = self.header().capacity as usize;
+        let first = std::cmp::min(src.len(), capacity - offset);
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr(), self.ring.add(offset), first);
+            std::ptr::copy_nonoverlapping(src.as_ptr().add(first), self.ring, src.len() - first);
+        }
+        (offset + src.len()) % capacity
+    }
+
+    /// Copies bytes from the ring at `offset` into `dst`, wrapping around the end
+    fn read_at(&self, offset: usize, dst: &mut [u8]) -> usize {
+        let capacity = self.header().capacity as usize;
+        let first = std::cmp::min(dst.len(), capacity - offset);
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.ring.add(offset), dst.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(
+                self.ring,
+                dst.as_mut_ptr().add(first),
+                dst.len() - first,
+            );
+        }
+        (offset + dst.len()) % capacity
+    }
+}
+
+/// Writing end of a single producer, single consumer channel
+pub struct Sender {
+    chan: Channel,
+}
+impl Sender {
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = ALIGN;
+
+    /// Size of a channel holding `capacity` bytes of messages at an address aligned to `ALIGN`
+    pub const fn size_for(capacity: usize) -> usize {
+        RING + capacity
+    }
+
+    /// Size required for a channel holding `capacity` bytes of messages, each message uses 4 extra bytes for its length
+    pub fn size_of(addr: Option<*mut u8>, capacity: usize) -> usize {
+        channel_size_of(addr, capacity)
+    }
+
+    /// Initializes a new channel in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8, capacity: usize) -> Result<(Self, usize)> {
+        let (chan, used) = Channel::new(mem, capacity)?;
+        Ok((Self { chan }, used))
+    }
+
+    /// Re-uses a channel from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        let (chan, used) = Channel::from_existing(mem)?;
+        Ok((Self { chan }, used))
+    }
+
+    /// Sends a message, waiting for the receiver to make room for it if the channel is full
+    pub fn send(&self, msg: &[u8], timeout: Timeout) -> Result<()> {
+        let header = self.chan.header();
+        let capacity = header.capacity as usize;
+        let needed = LEN_SIZE + msg.len();
+        if needed > capacity {
+            return Err(From::from(format!(
+                "Message of {} bytes does not fit in a channel of {} bytes",
+                msg.len(),
+                capacity
+            )));
+        }
+
+        let deadline = Deadline::new(timeout);
+        while capacity - (header.used.load(Ordering::Acquire) as usize) < needed {
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(From::from("Timed out waiting for space in channel")),
+            };
+            // A stale signal only causes another check of the free space
+            self.chan.space_available.wait(remaining)?;
+        }
+
+        let tail = header.tail.load(Ordering::Relaxed) as usize;
+        let tail = self.chan.write_at(tail, &(msg.len() as u32).to_ne_bytes());
+        let tail = self.chan.write_at(tail, msg);
+        header.tail.store(tail as u32, Ordering::Relaxed);
+        header.used.fetch_add(needed as u32, Ordering::Release);
+        trace!("spsc send({:p}) {} bytes", self.chan.header, msg.len());
+
+        self.chan.data_available.set(EventState::Signaled)
+    }
+}
+
+/// Reading end of a single producer, single consumer channel
+pub struct Receiver {
+    chan: Channel,
+}
+impl Receiver {
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = ALIGN;
+
+    /// Size of a channel holding `capacity` bytes of messages at an address aligned to `ALIGN`
+    pub const fn size_for(capacity: usize) -> usize {
+        RING + capacity
+    }
+
+    /// Size required for a channel holding `capacity` bytes of messages, each message uses 4 extra bytes for its length
+    pub fn size_of(addr: Option<*mut u8>, capacity: usize) -> usize {
+        channel_size_of(addr, capacity)
+    }
+
+    /// Initializes a new channel in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8, capacity: usize) -> Result<(Self, usize)> {
+        let (chan, used) = Channel::new(mem, capacity)?;
+        Ok((Self { chan }, used))
+    }
+
+    /// Re-uses a channel from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        let (chan, used) = Channel::from_existing(mem)?;
+        Ok((Self { chan }, used))
+    }
+
+    /// Receives the next message, waiting for the sender if the channel is empty
+    pub fn recv(&self, timeout: Timeout) -> Result<Vec<u8>> {
+        let header = self.chan.header();
+
+        let deadline = Deadline::new(timeout);
+        while header.used.load(Ordering::Acquire) == 0 {
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(From::from("Timed out waiting for data in channel")),
+            };
+            // A stale signal only causes another check of the used space
+            self.chan.data_available.wait(remaining)?;
+        }
+
+        let head = header.head.load(Ordering::Relaxed) as usize;
+        let mut len = [0u8; LEN_SIZE];
+        let head = self.chan.read_at(head, &mut len);
+        let mut msg = vec![0u8; u32::from_ne_bytes(len) as usize];
+        let head = self.chan.read_at(head, &mut msg);
+        header.head.store(head as u32, Ordering::Relaxed);
+        header
+            .used
+            .fetch_sub((LEN_SIZE + msg.len()) as u32, Ordering::Release);
+        trace!("spsc recv({:p}) {} bytes", self.chan.header, msg.len());
+
+        self.chan.space_available.set(EventState::Signaled)?;
+        Ok(msg)
+    }
+}
@@ -0,0 +1,124 @@
+use std::mem::{align_of, size_of};
+use std::ptr::null_mut;
+
+use super::{lock_internal, Condvar, EventImpl, EventState};
+use crate::locks::{LockImpl, LockInit, Mutex};
+use crate::{check_align, max_align, Deadline, Result, Timeout};
+
+/// Offsets of the condvar and of the pending, after the mutex
+const CONDVAR: usize = Mutex::SIZE.next_multiple_of(Condvar::ALIGN);
+const PENDING: usize = (CONDVAR + Condvar::SIZE).next_multiple_of(align_of::<u32>());
+
+/// Event that counts its signals : `signal_n(n)` lets exactly `n` waits return, where an
+/// auto reset `Event` collapses signals sent before a waiter consumed the previous one.
+pub struct CountingEvent {
+    mutex: Box<dyn LockImpl>,
+    condvar: Condvar,
+    pending: *mut u32,
+}
+
+impl CountingEvent {
+    /// Size of the event's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize = PENDING + size_of::<u32>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(Mutex::ALIGN, Condvar::ALIGN);
+
+    /// Size required for the event's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::SIZE
+    }
+
+    /// Initializes a new event without pending signals and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "CountingEvent")?;
+        let (mutex, _) = Mutex::new(mem, null_mut())?;
+        let (condvar, _) = Condvar::new(mem.add(CONDVAR))?;
+        let pending = mem.add(PENDING) as *mut u32;
+        pending.write(0);
+
+        let obj = Self {
+            mutex,
+            condvar,
+            pending,
+        };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Re-uses an event from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "CountingEvent")?;
+        let (mutex, _) = Mutex::from_existing(mem, null_mut())?;
+        let (condvar, _) = Condvar::from_existing(mem.add(CONDVAR))?;
+        let pending = mem.add(PENDING) as *mut u32;
+
+        let obj = Self {
+            mutex,
+            condvar,
+            pending,
+        };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Number of signals not consumed by a waiter yet
+    pub fn pending(&self) -> Result<u32> {
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+        let pending = unsafe { *self.pending };
+        drop(guard);
+        Ok(pending)
+    }
+
+    /// Adds `n` signals, each one lets a single `wait()` return
+    pub fn signal_n(&self, n: u32) -> Result<()> {
+        if n == 0 {
+            return Ok(());
+        }
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+        unsafe { *self.pending = (*self.pending).saturating_add(n) };
+        trace!("CountingEvent signal_n({:p}, {})", self.pending, n);
+        drop(guard);
+
+        // Waiters woken in excess find no signal left and go back to sleep
+        if n == 1 {
+            self.condvar.notify_one()
+        } else {
+            self.condvar.notify_all()
+        }
+    }
+}
+
+impl EventImpl for CountingEvent {
+    /// Waits for a signal and consumes it
+    fn wait(&self, timeout: Timeout) -> Result<()> {
+        let deadline = Deadline::new(timeout);
+        let guard = lock_internal(&*self.mutex, timeout)?;
+        let remaining = deadline
+            .remaining()
+            .unwrap_or(Timeout::Val(Default::default()));
+        self.condvar
+            .wait_while(&guard, |_| unsafe { *self.pending } == 0, remaining)?;
+        unsafe { *self.pending -= 1 };
+        drop(guard);
+        Ok(())
+    }
+
+    /// `Signaled` adds a single signal and `Clear` drops every pending signal
+    fn set(&self, state: EventState) -> Result<()> {
+        match state {
+            EventState::Signaled => self.signal_n(1),
+            EventState::Clear => {
+                let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+                unsafe { *self.pending = 0 };
+                drop(guard);
+                Ok(())
+            }
+        }
+    }
+}
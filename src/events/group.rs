@@ -0,0 +1,152 @@
+use std::mem::{align_of, size_of};
+use std::ptr::null_mut;
+
+use super::{lock_internal, Condvar};
+use crate::locks::{LockImpl, LockInit, Mutex};
+use crate::{check_align, max_align, Deadline, Result, Timeout};
+
+/// Offsets of the condvar and of the bits, after the mutex
+const CONDVAR: usize = Mutex::SIZE.next_multiple_of(Condvar::ALIGN);
+const BITS: usize = (CONDVAR + Condvar::SIZE).next_multiple_of(align_of::<u32>());
+
+/// Condition on the bits passed to `EventGroup::wait_bits()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitMode {
+    /// Returns as soon as one of the bits is set
+    Any,
+    /// Returns once every bit is set
+    All,
+}
+
+/// 32 bit flag word where every bit is an independent event, like RTOS event groups.
+/// Waiters can wait for any or all of a set of bits and optionally consume them.
+pub struct EventGroup {
+    mutex: Box<dyn LockImpl>,
+    condvar: Condvar,
+    bits: *mut u32,
+}
+
+impl EventGroup {
+    /// Size of the event group's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize = BITS + size_of::<u32>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(Mutex::ALIGN, Condvar::ALIGN);
+
+    /// Size required for the event group's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::SIZE
+    }
+
+    /// Initializes a new event group with every bit cleared and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "EventGroup")?;
+        let (mutex, _) = Mutex::new(mem, null_mut())?;
+        let (condvar, _) = Condvar::new(mem.add(CONDVAR))?;
+        let bits = mem.add(BITS) as *mut u32;
+        bits.write(0);
+
+        let obj = Self {
+            mutex,
+            condvar,
+            bits,
+        };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Re-uses an event group from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "EventGroup")?;
+        let (mutex, _) = Mutex::from_existing(mem, null_mut())?;
+        let (condvar, _) = Condvar::from_existing(mem.add(CONDVAR))?;
+        let bits = mem.add(BITS) as *mut u32;
+
+        let obj = Self {
+            mutex,
+            condvar,
+            bits,
+        };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Returns the current bits
+    pub fn bits(&self) -> Result<u32> {
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+        let bits = unsafe { *self.bits };
+        drop(guard);
+        Ok(bits)
+    }
+
+    /// Sets the bits of `mask`, waking the waiters they satisfy. Returns the new bits
+    pub fn set_bits(&self, mask: u32) -> Result<u32> {
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+        let bits = unsafe {
+            *self.bits |= mask;
+            *self.bits
+        };
+        trace!(
+            "EventGroup set_bits({:p}, 0x{:X}) = 0x{:X}",
+            self.bits,
+            mask,
+            bits
+        );
+        drop(guard);
+        self.condvar.notify_all()?;
+        Ok(bits)
+    }
+
+    /// Clears the bits of `mask`. Returns the new bits
+    pub fn clear_bits(&self, mask: u32) -> Result<u32> {
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+        let bits = unsafe {
+            *self.bits &= !mask;
+            *self.bits
+        };
+        trace!(
+            "EventGroup clear_bits({:p}, 0x{:X}) = 0x{:X}",
+            self.bits,
+            mask,
+            bits
+        );
+        drop(guard);
+        Ok(bits)
+    }
+
+    /// Waits until any or all the bits of `mask` are set and returns the bits as they were
+    /// when the condition was met. With `clear_on_exit`, the bits of `mask` are cleared
+    /// before returning so no other waiter sees them.
+    pub fn wait_bits(
+        &self,
+        mask: u32,
+        mode: WaitMode,
+        clear_on_exit: bool,
+        timeout: Timeout,
+    ) -> Result<u32> {
+        let satisfied = |bits: u32| match mode {
+            WaitMode::Any => bits & mask != 0,
+            WaitMode::All => bits & mask == mask,
+        };
+
+        let deadline = Deadline::new(timeout);
+        let guard = lock_internal(&*self.mutex, timeout)?;
+        let remaining = deadline
+            .remaining()
+            .unwrap_or(Timeout::Val(Default::default()));
+        self.condvar
+            .wait_while(&guard, |_| !satisfied(unsafe { *self.bits }), remaining)?;
+
+        let bits = unsafe { *self.bits };
+        if clear_on_exit {
+            unsafe { *self.bits &= !mask };
+        }
+        drop(guard);
+        Ok(bits)
+    }
+}
@@ -0,0 +1,104 @@
+use std::mem::{align_of, size_of};
+
+use super::{Event, EventImpl, EventInit, EventState};
+use crate::sync::{AtomicU32, Ordering};
+use crate::{check_align, max_align, Result, Timeout};
+
+/// Offset of the event, after the count
+const EVENT: usize = size_of::<AtomicU32>().next_multiple_of(Event::ALIGN);
+
+/// Countdown latch : `wait()` blocks until `count_down()` brought the count to zero.
+/// Unlike a barrier it never resets, once open every current and future waiter goes through.
+pub struct Latch {
+    count: *const AtomicU32,
+    open: Box<dyn EventImpl>,
+}
+
+impl Latch {
+    /// Size of the latch's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize = EVENT + Event::SIZE;
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(align_of::<AtomicU32>(), Event::ALIGN);
+
+    /// Size required for the latch's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::SIZE
+    }
+
+    /// Initializes a new latch waiting for `count` count downs and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8, count: u32) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Latch")?;
+        let ptr = mem as *mut AtomicU32;
+        ptr.write(AtomicU32::new(count));
+        let (open, _) = Event::new(mem.add(EVENT), false)?;
+        if count == 0 {
+            open.set(EventState::Signaled)?;
+        }
+
+        let obj = Self { count: ptr, open };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Re-uses a latch from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Latch")?;
+        let ptr = mem as *mut AtomicU32;
+        let (open, _) = Event::from_existing(mem.add(EVENT))?;
+
+        let obj = Self { count: ptr, open };
+        Ok((obj, Self::SIZE))
+    }
+
+    fn counter(&self) -> &AtomicU32 {
+        unsafe { &*self.count }
+    }
+
+    /// Number of count downs left before the latch opens
+    pub fn count(&self) -> u32 {
+        self.counter().load(Ordering::Acquire)
+    }
+
+    /// Decrements the count by `n`, stopping at zero, and opens the latch when it reaches zero.
+    /// Returns the count left.
+    pub fn count_down(&self, n: u32) -> Result<u32> {
+        let mut cur = self.counter().load(Ordering::Relaxed);
+        loop {
+            if cur == 0 {
+                return Ok(0);
+            }
+            let next = cur.saturating_sub(n);
+            match self.counter().compare_exchange_weak(
+                cur,
+                next,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(v) => cur = v,
+            }
+        }
+
+        let left = cur.saturating_sub(n);
+        trace!("Latch count_down({:p}, {}) = {}", self.count, n, left);
+        if left == 0 {
+            self.open.set(EventState::Signaled)?;
+        }
+        Ok(left)
+    }
+
+    /// Waits for the count to reach zero
+    pub fn wait(&self, timeout: Timeout) -> Result<()> {
+        if self.count() == 0 {
+            return Ok(());
+        }
+        self.open.wait(timeout)
+    }
+}
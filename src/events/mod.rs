@@ -9,17 +9,42 @@ cfg_if::cfg_if! {
         unimplemented!("This crate does not support your OS yet !");
     }
 }
+use crate::locks::{AbandonedError, LockGuard, LockImpl};
+use crate::mapped::{self, Mapped, Mapping};
+use crate::named::{self, Named};
 use crate::{Result, Timeout};
 pub use os::*;
 
+mod counting;
+pub use counting::*;
+mod group;
+pub use group::*;
+mod latch;
+pub use latch::*;
+
+/// Locks the internal mutex of an event. Its critical sections only update fields that stay
+/// valid if the owner dies, so a mutex abandoned by a dead owner is taken again
+pub(crate) fn lock_internal(mutex: &dyn LockImpl, timeout: Timeout) -> Result<LockGuard<'_>> {
+    match mutex.try_lock(timeout) {
+        Err(e) if e.is::<AbandonedError>() => mutex.try_lock(timeout),
+        res => res,
+    }
+}
+
 pub enum EventState {
     /// Clear's the event state so the next wait() call will block
     Clear,
-    /// Sets the event to the signaled state unblocking any waiters
+    /// Sets the event to the signaled state unblocking any waiters.
+    /// Signals sent to an auto reset event before a waiter consumed the previous one collapse, use `CountingEvent` to keep them
     Signaled,
 }
 
 pub trait EventInit {
+    /// Size of the event's internal representation at an address aligned to `ALIGN`
+    const SIZE: usize;
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    const ALIGN: usize;
+
     /// Size required for the event's internal representation
     fn size_of(addr: Option<*mut u8>) -> usize;
 
@@ -34,6 +59,68 @@ pub trait EventInit {
     /// This function is unsafe because it cannot guarantee that the provided memory is valid.
     #[allow(clippy::new_ret_no_self)]
     unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn EventImpl>, usize)>;
+
+    /// Creates the event in a new shared memory object called `name`.
+    /// The name is removed when the returned value is dropped.
+    fn create_named(name: &str, auto_reset: bool) -> Result<Named<Box<dyn EventImpl>>>
+    where
+        Self: Sized,
+    {
+        named::create(name, Self::size_of(None), |mem| unsafe {
+            Ok(Self::new(mem, auto_reset)?.0)
+        })
+    }
+
+    /// Opens an event created with `create_named()`
+    fn open_named(name: &str) -> Result<Named<Box<dyn EventImpl>>>
+    where
+        Self: Sized,
+    {
+        named::open(name, |mem| unsafe { Ok(Self::from_existing(mem)?.0) })
+    }
+
+    /// Initializes the event `offset` bytes into `map`.
+    /// The returned event borrows `map` so it cannot be used once unmapped.
+    fn create_in<M: Mapping>(
+        map: &M,
+        offset: usize,
+        auto_reset: bool,
+    ) -> Result<Mapped<'_, Box<dyn EventImpl>>>
+    where
+        Self: Sized,
+    {
+        let mem = mapped::at(map, offset, |_| Self::SIZE)?;
+        let event = unsafe { Self::new(mem, auto_reset)?.0 };
+        Ok(mapped::wrap(map, event))
+    }
+
+    /// Re-uses an event created with `create_in()` at the same offset of the same memory
+    fn open_in<M: Mapping>(map: &M, offset: usize) -> Result<Mapped<'_, Box<dyn EventImpl>>>
+    where
+        Self: Sized,
+    {
+        let mem = mapped::at(map, offset, |_| Self::SIZE)?;
+        let event = unsafe { Self::from_existing(mem)?.0 };
+        Ok(mapped::wrap(map, event))
+    }
+
+    /// Places the event `offset` bytes into `shmem`.
+    /// The owner of `shmem` initializes it and other processes open it.
+    #[cfg(feature = "shared_memory")]
+    fn in_shmem(
+        shmem: &shared_memory::Shmem,
+        offset: usize,
+        auto_reset: bool,
+    ) -> Result<Mapped<'_, Box<dyn EventImpl>>>
+    where
+        Self: Sized,
+    {
+        if shmem.is_owner() {
+            Self::create_in(shmem, offset, auto_reset)
+        } else {
+            Self::open_in(shmem, offset)
+        }
+    }
 }
 
 pub trait EventImpl {
@@ -43,10 +130,12 @@ pub trait EventImpl {
     fn set(&self, state: EventState) -> Result<()>;
 }
 
-use std::mem::size_of;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::mem::{align_of, size_of};
 use std::time;
 
+use crate::sync::{spin_loop, AtomicU8, Ordering};
+
+#[repr(C)]
 struct InnerBusy {
     signal: AtomicU8,
     auto_reset: u8,
@@ -55,17 +144,20 @@ pub struct BusyEvent {
     inner: *mut InnerBusy,
 }
 impl EventInit for BusyEvent {
+    const SIZE: usize = size_of::<InnerBusy>();
+    const ALIGN: usize = align_of::<InnerBusy>();
+
     fn size_of(_addr: Option<*mut u8>) -> usize {
         size_of::<InnerBusy>()
     }
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, auto_reset: bool) -> Result<(Box<dyn EventImpl>, usize)> {
         let ptr = mem as *mut InnerBusy;
+        ptr.write(InnerBusy {
+            signal: AtomicU8::new(0),
+            auto_reset: if auto_reset { 1 } else { 0 },
+        });
         let obj = Self { inner: ptr };
-        let inner = &mut *obj.inner;
-
-        inner.auto_reset = if auto_reset { 1 } else { 0 };
-        obj.set(EventState::Clear)?;
 
         Ok((Box::new(obj), Self::size_of(None)))
     }
@@ -73,7 +165,7 @@ impl EventInit for BusyEvent {
     unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn EventImpl>, usize)> {
         let ptr = mem as *mut InnerBusy;
         let obj = Self { inner: ptr };
-        let inner = &mut *obj.inner;
+        let inner = &*obj.inner;
 
         if inner.auto_reset > 1 || inner.signal.load(Ordering::Relaxed) > 1 {
             return Err(From::from("Existing BusyEvent is corrupted"));
@@ -82,11 +174,16 @@ impl EventInit for BusyEvent {
         Ok((Box::new(obj), Self::size_of(None)))
     }
 }
-fn busy_wait_auto(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
-    let mut prev_val = match signal.compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed) {
+/// Consumes the signal. Acquire pairs with the Release in `set()` so writes made
+/// before signaling are visible to the waiter
+fn try_consume(signal: &AtomicU8) -> u8 {
+    match signal.compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed) {
         Ok(v) => v,
         Err(v) => v,
-    };
+    }
+}
+fn busy_wait_auto(signal: &AtomicU8, timeout: Timeout) -> Result<()> {
+    let mut prev_val = try_consume(signal);
 
     if prev_val == 1 {
         return Ok(());
@@ -95,21 +192,15 @@ fn busy_wait_auto(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
         Timeout::Infinite => {
             // Busy loop until signaled
             while prev_val == 0 {
-                prev_val = match signal.compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
-                {
-                    Ok(v) => v,
-                    Err(v) => v,
-                };
+                spin_loop();
+                prev_val = try_consume(signal);
             }
         }
         Timeout::Val(d) => {
             let start = time::Instant::now();
             while prev_val == 0 && start.elapsed() < d {
-                prev_val = match signal.compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
-                {
-                    Ok(v) => v,
-                    Err(v) => v,
-                };
+                spin_loop();
+                prev_val = try_consume(signal);
             }
         }
     };
@@ -120,8 +211,8 @@ fn busy_wait_auto(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
         Err(From::from("Waiting for BusyEvent timed out !".to_string()))
     }
 }
-fn busy_wait_manual(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
-    let mut prev_val = signal.load(Ordering::Relaxed);
+fn busy_wait_manual(signal: &AtomicU8, timeout: Timeout) -> Result<()> {
+    let mut prev_val = signal.load(Ordering::Acquire);
     if prev_val == 1 {
         return Ok(());
     }
@@ -130,13 +221,15 @@ fn busy_wait_manual(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
         Timeout::Infinite => {
             // Busy loop until signaled
             while prev_val == 0 {
-                prev_val = signal.load(Ordering::Relaxed);
+                spin_loop();
+                prev_val = signal.load(Ordering::Acquire);
             }
         }
         Timeout::Val(d) => {
             let start = time::Instant::now();
             while prev_val == 0 && start.elapsed() < d {
-                prev_val = signal.load(Ordering::Relaxed);
+                spin_loop();
+                prev_val = signal.load(Ordering::Acquire);
             }
         }
     };
@@ -149,25 +242,27 @@ fn busy_wait_manual(signal: &mut AtomicU8, timeout: Timeout) -> Result<()> {
 }
 impl EventImpl for BusyEvent {
     fn wait(&self, timeout: Timeout) -> Result<()> {
-        let inner = unsafe { &mut *self.inner };
+        let inner = unsafe { &*self.inner };
         // Do a quick check first up
-        if inner.auto_reset == 1 {
-            busy_wait_auto(&mut inner.signal, timeout)
+        let res = if inner.auto_reset == 1 {
+            busy_wait_auto(&inner.signal, timeout)
         } else {
-            busy_wait_manual(&mut inner.signal, timeout)
-        }
+            busy_wait_manual(&inner.signal, timeout)
+        };
+        trace!("BusyEvent wait({:p}) = {}", self.inner, res.is_ok());
+        res
     }
 
     fn set(&self, state: EventState) -> Result<()> {
-        let inner = unsafe { &mut *self.inner };
+        let inner = unsafe { &*self.inner };
         match state {
             EventState::Clear => {
-                //trace!("ResetEvent({:p})", self.inner);
+                trace!("ResetEvent({:p})", self.inner);
                 inner.signal.store(0, Ordering::Relaxed);
             }
             EventState::Signaled => {
-                //trace!("SetEvent({:p})", self.inner);
-                inner.signal.store(1, Ordering::Relaxed);
+                trace!("SetEvent({:p})", self.inner);
+                inner.signal.store(1, Ordering::Release);
             }
         };
 
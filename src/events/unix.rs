@@ -1,4 +1,4 @@
-use std::mem::{size_of, MaybeUninit};
+use std::mem::{align_of, size_of, MaybeUninit};
 use std::ptr::null_mut;
 
 use libc::{
@@ -15,12 +15,38 @@ use libc::{
     pthread_condattr_t,
     PTHREAD_PROCESS_SHARED,
 };
-//use log::*;
 
 use crate::events::*;
 use crate::locks::*;
-use crate::{Result, Timeout};
+use crate::{check_align, max_align, Deadline, Result, Timeout};
 
+/// Initializes a process shared condition variable
+pub(crate) unsafe fn init_pshared_cond(cond: *mut pthread_cond_t) -> Result<()> {
+    let mut attrs = MaybeUninit::<pthread_condattr_t>::uninit();
+    trace!("pthread_condattr_init()");
+    if pthread_condattr_init(attrs.as_mut_ptr()) != 0 {
+        return Err(From::from(
+            "Failed to initialize pthread_condattr_init".to_string(),
+        ));
+    }
+    let mut attrs = attrs.assume_init();
+    trace!("pthread_condattr_setpshared()");
+    if pthread_condattr_setpshared(&mut attrs, PTHREAD_PROCESS_SHARED) != 0 {
+        return Err(From::from(
+            "Failed to set pthread_condattr_setpshared(PTHREAD_PROCESS_SHARED)".to_string(),
+        ));
+    }
+
+    trace!("pthread_cond_init({:p})", cond);
+    if pthread_cond_init(cond, &attrs) != 0 {
+        return Err(From::from(
+            "Failed to initialize pthread_cond_init".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[repr(C)]
 struct InnerEvent {
     cond: pthread_cond_t,
     auto_reset: u8,
@@ -31,6 +57,10 @@ pub struct Event {
     inner: *mut InnerEvent,
 }
 impl EventInit for Event {
+    const SIZE: usize =
+        Mutex::SIZE.next_multiple_of(size_of::<*mut u8>()) + size_of::<InnerEvent>();
+    const ALIGN: usize = max_align(Mutex::ALIGN, align_of::<InnerEvent>());
+
     fn size_of(addr: Option<*mut u8>) -> usize {
         let mutex_size = Mutex::size_of(addr);
         let padding = match addr {
@@ -42,41 +72,23 @@ impl EventInit for Event {
 
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, auto_reset: bool) -> Result<(Box<dyn EventImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Event")?;
         let (mutex, used_bytes) = Mutex::new(mem, null_mut())?;
         let ptr = mem.add(used_bytes);
         let ptr = ptr.add(ptr.align_offset(size_of::<*mut u8>() as _)) as *mut InnerEvent;
         let inner = &mut *ptr;
 
-        #[allow(clippy::uninit_assumed_init)]
-        let mut attrs: pthread_condattr_t = MaybeUninit::uninit().assume_init();
-        //trace!("pthread_condattr_init()");
-        if pthread_condattr_init(&mut attrs) != 0 {
-            return Err(From::from(
-                "Failed to initialize pthread_condattr_init".to_string(),
-            ));
-        }
-        //trace!("pthread_condattr_setpshared()");
-        if pthread_condattr_setpshared(&mut attrs, PTHREAD_PROCESS_SHARED) != 0 {
-            return Err(From::from(
-                "Failed to set pthread_condattr_setpshared(PTHREAD_PROCESS_SHARED)".to_string(),
-            ));
-        }
-
-        //trace!("pthread_cond_init({:p})", ptr);
-        if pthread_cond_init(&mut inner.cond, &attrs) != 0 {
-            return Err(From::from(
-                "Failed to initialize pthread_cond_init".to_string(),
-            ));
-        }
+        init_pshared_cond(&mut inner.cond)?;
         inner.auto_reset = if auto_reset { 1 } else { 0 };
         inner.signal = 0;
 
         let obj = Box::new(Self { mutex, inner });
 
-        Ok((obj, (ptr as usize - mem as usize) + Self::size_of(None)))
+        Ok((obj, (ptr as usize - mem as usize) + size_of::<InnerEvent>()))
     }
 
     unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn EventImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Event")?;
         let (mutex, used_bytes) = Mutex::from_existing(mem, null_mut())?;
         let ptr = mem.add(used_bytes);
         let ptr = ptr.add(ptr.align_offset(size_of::<*mut u8>() as _)) as *mut InnerEvent;
@@ -89,17 +101,17 @@ impl EventInit for Event {
 
         let obj = Box::new(Self { mutex, inner });
 
-        Ok((obj, (ptr as usize - mem as usize) + Self::size_of(None)))
+        Ok((obj, (ptr as usize - mem as usize) + size_of::<InnerEvent>()))
     }
 }
 
 impl EventImpl for Event {
     fn wait(&self, timeout: Timeout) -> Result<()> {
         let (guard, timespec) = match timeout {
-            Timeout::Infinite => (self.mutex.lock()?, None),
+            Timeout::Infinite => (lock_internal(&*self.mutex, Timeout::Infinite)?, None),
             Timeout::Val(d) => {
                 let timespec = abs_timespec_from_duration(d);
-                (self.mutex.try_lock(timeout)?, Some(timespec))
+                (lock_internal(&*self.mutex, timeout)?, Some(timespec))
             }
         };
 
@@ -110,6 +122,7 @@ impl EventImpl for Event {
                 res = unsafe {
                     pthread_cond_timedwait(&mut inner.cond, self.mutex.as_raw() as _, &ts)
                 };
+                res = consistent_if_owner_dead(self.mutex.as_raw() as _, res);
                 if res != 0 {
                     break;
                 }
@@ -117,12 +130,15 @@ impl EventImpl for Event {
         } else {
             while inner.signal != 1 {
                 res = unsafe { pthread_cond_wait(&mut inner.cond, self.mutex.as_raw() as _) };
+                res = consistent_if_owner_dead(self.mutex.as_raw() as _, res);
                 if res != 0 {
                     break;
                 }
             }
         }
 
+        trace!("pthread_cond_wait({:p}) = {}", &inner.cond, res);
+
         // Success
         let ret = if res == 0 {
             if inner.auto_reset == 1 {
@@ -138,11 +154,11 @@ impl EventImpl for Event {
     }
 
     fn set(&self, state: EventState) -> Result<()> {
-        let guard = self.mutex.lock()?;
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
         let inner = unsafe { &mut *self.inner };
         let res = match state {
             EventState::Clear => {
-                //trace!("reset pthread_cond({:p})", &inner.cond);
+                trace!("reset pthread_cond({:p})", &inner.cond);
                 inner.signal = 0;
                 0
             }
@@ -150,10 +166,10 @@ impl EventImpl for Event {
                 inner.signal = 1;
                 unsafe {
                     if inner.auto_reset == 1 {
-                        //trace!("pthread_cond_signal({:p})", &inner.cond);
+                        trace!("pthread_cond_signal({:p})", &inner.cond);
                         pthread_cond_signal(&mut inner.cond)
                     } else {
-                        //trace!("pthread_cond_broadcast({:p})", &inner.cond);
+                        trace!("pthread_cond_broadcast({:p})", &inner.cond);
                         pthread_cond_broadcast(&mut inner.cond)
                     }
                 }
@@ -171,3 +187,242 @@ impl EventImpl for Event {
         }
     }
 }
+
+#[repr(C)]
+struct InnerBroadcast {
+    cond: pthread_cond_t,
+    generation: u64,
+}
+/// Generation counted notification. Every `notify()` increments the generation and wakes
+/// all the processes waiting for it to change, late subscribers see the new generation
+/// instead of missing the notification.
+pub struct Broadcast {
+    mutex: Box<dyn LockImpl>,
+    inner: *mut InnerBroadcast,
+}
+impl Broadcast {
+    /// Size of the broadcast's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize =
+        Mutex::SIZE.next_multiple_of(size_of::<*mut u8>()) + size_of::<InnerBroadcast>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(Mutex::ALIGN, align_of::<InnerBroadcast>());
+
+    /// Size required for the broadcast's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let mutex_size = Mutex::size_of(addr);
+        let padding = match addr {
+            Some(mem) => unsafe { mem.add(mutex_size).align_offset(size_of::<*mut u8>() as _) },
+            None => 0,
+        };
+        mutex_size + padding + size_of::<InnerBroadcast>()
+    }
+
+    /// Initializes a new broadcast in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Broadcast")?;
+        let (mutex, used_bytes) = Mutex::new(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<*mut u8>() as _)) as *mut InnerBroadcast;
+        let inner = &mut *ptr;
+
+        init_pshared_cond(&mut inner.cond)?;
+        inner.generation = 0;
+
+        let obj = Self { mutex, inner };
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerBroadcast>(),
+        ))
+    }
+
+    /// Re-uses a broadcast from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Broadcast")?;
+        let (mutex, used_bytes) = Mutex::from_existing(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<*mut u8>() as _)) as *mut InnerBroadcast;
+
+        let obj = Self { mutex, inner: ptr };
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerBroadcast>(),
+        ))
+    }
+
+    /// Returns the current generation
+    pub fn generation(&self) -> Result<u64> {
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+        let generation = unsafe { (*self.inner).generation };
+        drop(guard);
+        Ok(generation)
+    }
+
+    /// Increments the generation and wakes every waiter. Returns the new generation
+    pub fn notify(&self) -> Result<u64> {
+        let guard = lock_internal(&*self.mutex, Timeout::Infinite)?;
+        let inner = unsafe { &mut *self.inner };
+        inner.generation = inner.generation.wrapping_add(1);
+        let generation = inner.generation;
+        trace!("pthread_cond_broadcast({:p})", &inner.cond);
+        let res = unsafe { pthread_cond_broadcast(&mut inner.cond) };
+        drop(guard);
+
+        if res != 0 {
+            return Err(From::from(format!(
+                "Failed to notify broadcast : 0x{:X}",
+                res
+            )));
+        }
+        Ok(generation)
+    }
+
+    /// Waits until the generation differs from `last_seen` and returns the new generation
+    pub fn wait_for_change(&self, last_seen: u64, timeout: Timeout) -> Result<u64> {
+        let (guard, timespec) = match timeout {
+            Timeout::Infinite => (lock_internal(&*self.mutex, Timeout::Infinite)?, None),
+            Timeout::Val(d) => {
+                let timespec = abs_timespec_from_duration(d);
+                (lock_internal(&*self.mutex, timeout)?, Some(timespec))
+            }
+        };
+
+        let inner = unsafe { &mut *self.inner };
+        let mut res = 0;
+        while inner.generation == last_seen {
+            res = unsafe {
+                match timespec {
+                    Some(ref ts) => {
+                        pthread_cond_timedwait(&mut inner.cond, self.mutex.as_raw() as _, ts)
+                    }
+                    None => pthread_cond_wait(&mut inner.cond, self.mutex.as_raw() as _),
+                }
+            };
+            res = consistent_if_owner_dead(self.mutex.as_raw() as _, res);
+            if res != 0 {
+                break;
+            }
+        }
+        trace!("pthread_cond_wait({:p}) = {}", &inner.cond, res);
+        let generation = inner.generation;
+        drop(guard);
+
+        if res != 0 {
+            return Err(From::from(format!(
+                "Failed waiting for broadcast : {}",
+                res
+            )));
+        }
+        Ok(generation)
+    }
+}
+
+/// Process shared condition variable that can be used with any `Mutex` from this crate,
+/// including a `Mutex` wrapped in `Poison`. Locks tracked by a `LockRegistry` are refused as
+/// waiting would release them without the registry knowing.
+pub struct Condvar {
+    cond: *mut pthread_cond_t,
+}
+impl Condvar {
+    /// Size of the condvar's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize = AbiTag::tagged_size(size_of::<pthread_cond_t>());
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = TAG_ALIGN;
+
+    /// Size required for the condvar's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        AbiTag::size_of(addr, size_of::<pthread_cond_t>())
+    }
+
+    /// Initializes a new condvar in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Condvar")?;
+        let cond = AbiTag::write(mem, AbiKind::Cond, size_of::<pthread_cond_t>()) as *mut _;
+        init_pshared_cond(cond)?;
+        Ok((
+            Self { cond },
+            (cond as usize - mem as usize) + size_of::<pthread_cond_t>(),
+        ))
+    }
+
+    /// Re-uses a condvar from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Condvar")?;
+        let cond = AbiTag::check(mem, AbiKind::Cond, size_of::<pthread_cond_t>())? as *mut _;
+        Ok((
+            Self { cond },
+            (cond as usize - mem as usize) + size_of::<pthread_cond_t>(),
+        ))
+    }
+
+    /// Atomically releases the guard's lock and waits for a notification. The lock is held
+    /// again when this returns, spurious wakeups are possible. Returns `AbandonedError` with
+    /// the lock held if its previous owner died holding it.
+    pub fn wait(&self, guard: &LockGuard, timeout: Timeout) -> Result<()> {
+        let mutex = match guard.lock_impl().as_mutex() {
+            Some(m) => m as *mut libc::pthread_mutex_t,
+            None => return Err(From::from("Condvar can only be used with a Mutex")),
+        };
+        let res = unsafe {
+            match timeout {
+                Timeout::Infinite => pthread_cond_wait(self.cond, mutex),
+                Timeout::Val(d) => {
+                    pthread_cond_timedwait(self.cond, mutex, &abs_timespec_from_duration(d))
+                }
+            }
+        };
+        trace!("pthread_cond_wait({:p}, {:p}) = {}", self.cond, mutex, res);
+
+        if res != 0 && consistent_if_owner_dead(mutex, res) == 0 {
+            return Err(Box::new(AbandonedError));
+        }
+        match res {
+            0 => Ok(()),
+            libc::ETIMEDOUT => Err(Box::new(TimeoutError { what: "condvar" })),
+            _ => Err(From::from(format!("Failed waiting for condvar : {}", res))),
+        }
+    }
+
+    /// Waits until `condition` returns false for the data protected by the guard's lock
+    pub fn wait_while<F: FnMut(*mut u8) -> bool>(
+        &self,
+        guard: &LockGuard,
+        mut condition: F,
+        timeout: Timeout,
+    ) -> Result<()> {
+        let deadline = Deadline::new(timeout);
+        while condition(**guard) {
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(Box::new(TimeoutError { what: "condvar" })),
+            };
+            self.wait(guard, remaining)?;
+        }
+        Ok(())
+    }
+
+    /// Wakes up one waiter
+    pub fn notify_one(&self) -> Result<()> {
+        trace!("pthread_cond_signal({:p})", self.cond);
+        match unsafe { pthread_cond_signal(self.cond) } {
+            0 => Ok(()),
+            res => Err(From::from(format!("Failed to notify condvar : {}", res))),
+        }
+    }
+
+    /// Wakes up every waiter
+    pub fn notify_all(&self) -> Result<()> {
+        trace!("pthread_cond_broadcast({:p})", self.cond);
+        match unsafe { pthread_cond_broadcast(self.cond) } {
+            0 => Ok(()),
+            res => Err(From::from(format!("Failed to notify condvar : {}", res))),
+        }
+    }
+}
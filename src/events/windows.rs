@@ -1,42 +1,58 @@
 use std::ffi::CString;
-use std::mem::size_of;
+use std::mem::{align_of, size_of};
 use std::ptr::null_mut;
 
 use winapi::{
-    shared::ntdef::{FALSE, NULL, TRUE},
+    shared::{
+        ntdef::{FALSE, NULL, TRUE},
+        winerror::WAIT_TIMEOUT,
+    },
     um::{
         handleapi::CloseHandle,
-        synchapi::{CreateEventA, OpenEventA, ResetEvent, SetEvent, WaitForSingleObject},
-        winbase::{INFINITE, WAIT_OBJECT_0},
-        winnt::{EVENT_MODIFY_STATE, HANDLE, SYNCHRONIZE},
+        synchapi::{
+            CreateEventA, OpenEventA, ReleaseSemaphore, ResetEvent, SetEvent, WaitForSingleObject,
+        },
+        winbase::{CreateSemaphoreA, OpenSemaphoreA, INFINITE, WAIT_OBJECT_0},
+        winnt::{EVENT_MODIFY_STATE, HANDLE, SEMAPHORE_MODIFY_STATE, SYNCHRONIZE},
     },
 };
 
 use super::{EventImpl, EventInit, EventState};
-use crate::{Result, Timeout};
+use crate::locks::{
+    ignore_poison, AbandonedError, LockGuard, LockImpl, LockInit, Mutex, TimeoutError,
+};
+use crate::{check_align, max_align, Deadline, Result, Timeout};
 
 pub struct Event {
     handle: HANDLE,
 }
 impl Drop for Event {
     fn drop(&mut self) {
-        //trace!("CloseHandle(0x{:X})", self.handle as usize);
+        trace!("CloseHandle(0x{:X})", self.handle as usize);
         unsafe { CloseHandle(self.handle) };
     }
 }
 impl EventInit for Event {
+    const SIZE: usize = size_of::<u32>();
+    const ALIGN: usize = align_of::<u32>();
+
     fn size_of(_addr: Option<*mut u8>) -> usize {
         size_of::<u32>()
     }
 
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, auto_reset: bool) -> Result<(Box<dyn EventImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Event")?;
         let mut handle: HANDLE = NULL;
         let mut id: u32 = 0;
         while handle == NULL {
             id = rand::random::<u32>();
             let path = CString::new(format!("event_{}", id)).unwrap();
-            //trace!("CreateEventA(NULL, '{:?}', '{}')",!auto_reset,path.to_string_lossy());
+            trace!(
+                "CreateEventA(NULL, '{:?}', '{}')",
+                !auto_reset,
+                path.to_string_lossy()
+            );
             handle = CreateEventA(
                 null_mut(),
                 if auto_reset { FALSE } else { TRUE } as _,
@@ -51,9 +67,10 @@ impl EventInit for Event {
     }
 
     unsafe fn from_existing(mem: *mut u8) -> Result<(Box<dyn EventImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Event")?;
         let id: u32 = *(mem as *mut u32);
         let path = CString::new(format!("event_{}", id)).unwrap();
-        //trace!("OpenEventA('{}')", path.to_string_lossy());
+        trace!("OpenEventA('{}')", path.to_string_lossy());
         let handle = OpenEventA(
             EVENT_MODIFY_STATE | SYNCHRONIZE, // request full access
             FALSE as _,                       // handle not inheritable
@@ -72,7 +89,6 @@ impl EventInit for Event {
 }
 impl EventImpl for Event {
     fn wait(&self, timeout: Timeout) -> Result<()> {
-        //trace!("WaitForSingleObject(0x{:X})", self.handle as usize);
         let wait_res = unsafe {
             WaitForSingleObject(
                 self.handle,
@@ -82,6 +98,11 @@ impl EventImpl for Event {
                 },
             )
         };
+        trace!(
+            "WaitForSingleObject(0x{:X}) = 0x{:X}",
+            self.handle as usize,
+            wait_res
+        );
 
         if wait_res == WAIT_OBJECT_0 {
             Ok(())
@@ -96,11 +117,11 @@ impl EventImpl for Event {
     fn set(&self, state: EventState) -> Result<()> {
         let res = match state {
             EventState::Clear => {
-                //trace!("ResetEvent(0x{:X})", self.handle as usize);
+                trace!("ResetEvent(0x{:X})", self.handle as usize);
                 unsafe { ResetEvent(self.handle) }
             }
             EventState::Signaled => {
-                //trace!("SetEvent(0x{:X})", self.handle as usize);
+                trace!("SetEvent(0x{:X})", self.handle as usize);
                 unsafe { SetEvent(self.handle) }
             }
         };
@@ -115,3 +136,394 @@ impl EventImpl for Event {
         }
     }
 }
+
+/// Named semaphore whose id is stored in shared memory
+pub(crate) struct Semaphore {
+    handle: HANDLE,
+}
+impl Drop for Semaphore {
+    fn drop(&mut self) {
+        trace!("CloseHandle(0x{:X})", self.handle as usize);
+        unsafe { CloseHandle(self.handle) };
+    }
+}
+impl Semaphore {
+    /// Creates a semaphore with a random name and writes its id to `id`
+    pub(crate) unsafe fn new(id: *mut u32) -> Result<Self> {
+        let mut handle: HANDLE = NULL;
+        let mut sem_id: u32 = 0;
+        while handle == NULL {
+            sem_id = rand::random::<u32>();
+            let path = CString::new(format!("semaphore_{}", sem_id)).unwrap();
+            trace!(
+                "CreateSemaphoreA(NULL, 0, MAX, '{}')",
+                path.to_string_lossy()
+            );
+            handle = CreateSemaphoreA(null_mut(), 0, i32::MAX, path.as_ptr() as *mut _);
+        }
+        *id = sem_id;
+        Ok(Self { handle })
+    }
+
+    pub(crate) unsafe fn from_existing(id: *mut u32) -> Result<Self> {
+        let path = CString::new(format!("semaphore_{}", *id)).unwrap();
+        trace!("OpenSemaphoreA('{}')", path.to_string_lossy());
+        let handle = OpenSemaphoreA(
+            SEMAPHORE_MODIFY_STATE | SYNCHRONIZE,
+            FALSE as _,
+            path.as_ptr() as *mut _,
+        );
+        if handle == NULL {
+            return Err(From::from(format!(
+                "Failed to open semaphore {}",
+                path.to_string_lossy()
+            )));
+        }
+        Ok(Self { handle })
+    }
+
+    /// Returns false if the timeout expired
+    pub(crate) fn wait(&self, timeout: Timeout) -> Result<bool> {
+        let wait_res = unsafe {
+            WaitForSingleObject(
+                self.handle,
+                match timeout {
+                    Timeout::Infinite => INFINITE,
+                    Timeout::Val(dur) => dur.as_millis() as _,
+                },
+            )
+        };
+        trace!(
+            "WaitForSingleObject(0x{:X}) = 0x{:X}",
+            self.handle as usize,
+            wait_res
+        );
+        if wait_res == WAIT_OBJECT_0 {
+            Ok(true)
+        } else if wait_res == WAIT_TIMEOUT {
+            Ok(false)
+        } else {
+            Err(From::from(format!(
+                "Failed waiting for semaphore : 0x{:X}",
+                wait_res
+            )))
+        }
+    }
+
+    pub(crate) fn release(&self, count: u32) -> Result<()> {
+        trace!("ReleaseSemaphore(0x{:X}, {})", self.handle as usize, count);
+        if count == 0 || unsafe { ReleaseSemaphore(self.handle, count as _, null_mut()) } != 0 {
+            Ok(())
+        } else {
+            Err(From::from("Failed to release semaphore".to_string()))
+        }
+    }
+}
+
+#[repr(C)]
+struct InnerBroadcast {
+    sem_id: u32,
+    /// Number of waiters that have not been released yet
+    waiters: u32,
+    generation: u64,
+}
+/// Generation counted notification. Every `notify()` increments the generation and wakes
+/// all the processes waiting for it to change, late subscribers see the new generation
+/// instead of missing the notification.
+pub struct Broadcast {
+    mutex: Box<dyn LockImpl>,
+    sem: Semaphore,
+    inner: *mut InnerBroadcast,
+}
+impl Broadcast {
+    /// Size of the broadcast's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize =
+        Mutex::SIZE.next_multiple_of(align_of::<u64>()) + size_of::<InnerBroadcast>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(Mutex::ALIGN, align_of::<u64>());
+
+    /// Size required for the broadcast's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let mutex_size = Mutex::size_of(addr);
+        let padding = match addr {
+            Some(mem) => unsafe { mem.add(mutex_size).align_offset(size_of::<u64>() as _) },
+            None => 0,
+        };
+        mutex_size + padding + size_of::<InnerBroadcast>()
+    }
+
+    /// Initializes a new broadcast in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Broadcast")?;
+        let (mutex, used_bytes) = Mutex::new(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<u64>() as _)) as *mut InnerBroadcast;
+        let inner = &mut *ptr;
+
+        let sem = Semaphore::new(&mut inner.sem_id)?;
+        inner.waiters = 0;
+        inner.generation = 0;
+
+        let obj = Self {
+            mutex,
+            sem,
+            inner: ptr,
+        };
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerBroadcast>(),
+        ))
+    }
+
+    /// Re-uses a broadcast from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Broadcast")?;
+        let (mutex, used_bytes) = Mutex::from_existing(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<u64>() as _)) as *mut InnerBroadcast;
+        let sem = Semaphore::from_existing(&mut (*ptr).sem_id)?;
+
+        let obj = Self {
+            mutex,
+            sem,
+            inner: ptr,
+        };
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerBroadcast>(),
+        ))
+    }
+
+    /// Returns the current generation
+    pub fn generation(&self) -> Result<u64> {
+        let guard = self.mutex.lock()?;
+        let generation = unsafe { (*self.inner).generation };
+        drop(guard);
+        Ok(generation)
+    }
+
+    /// Increments the generation and wakes every waiter. Returns the new generation
+    pub fn notify(&self) -> Result<u64> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        inner.generation = inner.generation.wrapping_add(1);
+        let res = self.sem.release(inner.waiters);
+        inner.waiters = 0;
+        let generation = inner.generation;
+        drop(guard);
+        res.map(|_| generation)
+    }
+
+    /// Waits until the generation differs from `last_seen` and returns the new generation
+    pub fn wait_for_change(&self, last_seen: u64, timeout: Timeout) -> Result<u64> {
+        let deadline = Deadline::new(timeout);
+        let remaining = match deadline.remaining() {
+            Some(t) => t,
+            None => return Err(From::from("Timed out waiting for broadcast")),
+        };
+        let guard = self.mutex.try_lock(remaining)?;
+        let inner = unsafe { &mut *self.inner };
+        if inner.generation != last_seen {
+            return Ok(inner.generation);
+        }
+        inner.waiters += 1;
+        drop(guard);
+
+        loop {
+            let signaled = self.sem.wait(
+                deadline
+                    .remaining()
+                    .unwrap_or(Timeout::Val(Default::default())),
+            )?;
+            let guard = self.mutex.lock()?;
+            let inner = unsafe { &mut *self.inner };
+            // notify() resets the count of waiters it released
+            if inner.generation != last_seen {
+                return Ok(inner.generation);
+            }
+            if !signaled {
+                // No notify happened since we registered so we are still counted
+                inner.waiters -= 1;
+                drop(guard);
+                return Err(From::from("Timed out waiting for broadcast"));
+            }
+            // A token left over from a waiter that timed out, we are still counted
+            drop(guard);
+        }
+    }
+}
+
+#[repr(C)]
+struct InnerCondvar {
+    sem_id: u32,
+    /// Number of waiters that have not been released yet
+    waiters: u32,
+}
+/// Process shared condition variable that can be used with any `Mutex` from this crate,
+/// including a `Mutex` wrapped in `Poison`. Locks tracked by a `LockRegistry` are refused as
+/// waiting would release them without the registry knowing.
+pub struct Condvar {
+    mutex: Box<dyn LockImpl>,
+    sem: Semaphore,
+    inner: *mut InnerCondvar,
+}
+impl Condvar {
+    /// Size of the condvar's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize =
+        Mutex::SIZE.next_multiple_of(align_of::<u32>()) + size_of::<InnerCondvar>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(Mutex::ALIGN, align_of::<u32>());
+
+    /// Size required for the condvar's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let mutex_size = Mutex::size_of(addr);
+        let padding = match addr {
+            Some(mem) => unsafe { mem.add(mutex_size).align_offset(size_of::<u32>() as _) },
+            None => 0,
+        };
+        mutex_size + padding + size_of::<InnerCondvar>()
+    }
+
+    /// Initializes a new condvar in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Condvar")?;
+        let (mutex, used_bytes) = Mutex::new(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<u32>() as _)) as *mut InnerCondvar;
+        let inner = &mut *ptr;
+
+        let sem = Semaphore::new(&mut inner.sem_id)?;
+        inner.waiters = 0;
+
+        let obj = Self {
+            mutex,
+            sem,
+            inner: ptr,
+        };
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerCondvar>(),
+        ))
+    }
+
+    /// Re-uses a condvar from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "Condvar")?;
+        let (mutex, used_bytes) = Mutex::from_existing(mem, null_mut())?;
+        let ptr = mem.add(used_bytes);
+        let ptr = ptr.add(ptr.align_offset(size_of::<u32>() as _)) as *mut InnerCondvar;
+        let sem = Semaphore::from_existing(&mut (*ptr).sem_id)?;
+
+        let obj = Self {
+            mutex,
+            sem,
+            inner: ptr,
+        };
+        Ok((
+            obj,
+            (ptr as usize - mem as usize) + size_of::<InnerCondvar>(),
+        ))
+    }
+
+    /// Atomically releases the guard's lock and waits for a notification. The lock is held
+    /// again when this returns, spurious wakeups are possible. Returns `AbandonedError` with
+    /// the lock held if its previous owner died holding it.
+    pub fn wait(&self, guard: &LockGuard, timeout: Timeout) -> Result<()> {
+        let lock = guard.lock_impl();
+        if lock.as_mutex().is_none() {
+            return Err(From::from("Condvar can only be used with a Mutex"));
+        }
+
+        // Registering before releasing the lock means a notify sent after we release it
+        // always leaves a token for us in the semaphore
+        let internal = self.mutex.lock()?;
+        unsafe { (*self.inner).waiters += 1 };
+        drop(internal);
+        lock.release()?;
+
+        let res = self.sem.wait(timeout);
+        let res = match res {
+            Ok(true) => Ok(()),
+            Ok(false) | Err(_) => {
+                let internal = self.mutex.lock()?;
+                // A notify may have released a token for us right after the timeout
+                if let Ok(true) = self.sem.wait(Timeout::Val(Default::default())) {
+                    drop(internal);
+                    Ok(())
+                } else {
+                    unsafe { (*self.inner).waiters -= 1 };
+                    drop(internal);
+                    res.and(Err(Box::new(TimeoutError { what: "condvar" })))
+                }
+            }
+        };
+
+        // The caller's guard still owns the lock. An abandoned Mutex is released before
+        // AbandonedError is returned, take it again so the guard stays valid
+        let mut abandoned = false;
+        loop {
+            match ignore_poison(lock.lock_checked()) {
+                Ok(g) => {
+                    std::mem::forget(g);
+                    break;
+                }
+                Err(e) if e.is::<AbandonedError>() => abandoned = true,
+                Err(e) => return Err(e),
+            }
+        }
+        if abandoned {
+            return Err(Box::new(AbandonedError));
+        }
+        res
+    }
+
+    /// Waits until `condition` returns false for the data protected by the guard's lock
+    pub fn wait_while<F: FnMut(*mut u8) -> bool>(
+        &self,
+        guard: &LockGuard,
+        mut condition: F,
+        timeout: Timeout,
+    ) -> Result<()> {
+        let deadline = Deadline::new(timeout);
+        while condition(**guard) {
+            let remaining = match deadline.remaining() {
+                Some(t) => t,
+                None => return Err(Box::new(TimeoutError { what: "condvar" })),
+            };
+            self.wait(guard, remaining)?;
+        }
+        Ok(())
+    }
+
+    /// Wakes up one waiter
+    pub fn notify_one(&self) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        let res = if inner.waiters > 0 {
+            inner.waiters -= 1;
+            self.sem.release(1)
+        } else {
+            Ok(())
+        };
+        drop(guard);
+        res
+    }
+
+    /// Wakes up every waiter
+    pub fn notify_all(&self) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        let res = self.sem.release(inner.waiters);
+        inner.waiters = 0;
+        drop(guard);
+        res
+    }
+}
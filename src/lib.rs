@@ -1,10 +1,80 @@
 pub(crate) type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// Diagnostics are forwarded to `tracing` when the feature is enabled and compiled out otherwise
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+    };
+}
+
+/// C bindings
+#[cfg(feature = "capi")]
+pub mod capi;
+/// Channels laid out in shared memory
+pub mod channel;
 /// Event implementations
 pub mod events;
 /// Lock implementations
 pub mod locks;
+/// Primitives placed in memory mappings of other crates
+pub mod mapped;
+/// Primitives created and opened by name
+pub mod named;
+/// One time initialization shared between processes
+pub mod once;
+/// Several primitives packed in one segment
+pub mod region;
+mod sync;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Timeout {
     Infinite,
     Val(std::time::Duration),
 }
+
+/// Fails unless `mem` is aligned to `align` bytes, as `new()` and `from_existing()` require
+pub(crate) fn check_align(mem: *mut u8, align: usize, what: &str) -> Result<()> {
+    if (mem as usize) % align != 0 {
+        return Err(From::from(format!(
+            "{} memory at {:p} must be aligned to {} bytes",
+            what, mem, align
+        )));
+    }
+    Ok(())
+}
+
+/// Greater of two alignments, usable in constants
+pub(crate) const fn max_align(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Tracks the time left from a `Timeout` across several blocking calls
+pub(crate) struct Deadline(Option<std::time::Instant>);
+impl Deadline {
+    pub(crate) fn new(timeout: Timeout) -> Self {
+        match timeout {
+            Timeout::Infinite => Self(None),
+            Timeout::Val(d) => Self(Some(std::time::Instant::now() + d)),
+        }
+    }
+    /// Returns the time left or None once the deadline has passed
+    pub(crate) fn remaining(&self) -> Option<Timeout> {
+        match self.0 {
+            None => Some(Timeout::Infinite),
+            Some(end) => end
+                .checked_duration_since(std::time::Instant::now())
+                .map(Timeout::Val),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::cell::UnsafeCell;
+use std::ffi::{CStr, CString};
+use std::mem::{align_of, size_of};
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+use libc::{c_short, flock, off_t, F_OFD_SETLK, F_OFD_SETLKW, F_RDLCK, F_UNLCK, F_WRLCK, SEEK_SET};
+
+use super::{LockGuard, LockImpl, LockInit, ReadLockGuard, TimeoutError};
+use crate::{check_align, Deadline, Result, Timeout};
+
+/// Longest sleep between two attempts of a timed acquisition
+const MAX_POLL: Duration = Duration::from_millis(10);
+
+/// Longest path of a lock file created by `with_range()`, NUL included
+const MAX_PATH: usize = 240;
+
+/// Rejects ranges whose bounds do not fit in the `off_t` given to fcntl()
+fn check_range(range: &Range<u64>) -> Result<()> {
+    if range.start > i64::MAX as u64 || range.end > i64::MAX as u64 {
+        return Err(From::from(format!(
+            "FileLock range {:?} does not fit in a file offset",
+            range
+        )));
+    }
+    Ok(())
+}
+
+/// What `with_range()` stores in the shared memory
+#[repr(C)]
+struct FileLockInfo {
+    /// First locked byte
+    start: u64,
+    /// Number of locked bytes, 0 to lock until the end of the file
+    len: u64,
+    /// NUL terminated path of the lock file
+    path: [u8; MAX_PATH],
+}
+
+/// Lock on a byte range of a file using open file description locks (`F_OFD_SETLK`).
+/// The kernel releases it when its owner dies, and `rlock()` takes a shared lock so
+/// readers can hold it together.
+///
+/// Locks belong to the file description opened by each `FileLock`, so they exclude other
+/// `FileLock` instances but not threads sharing the same instance.
+pub struct FileLock {
+    fd: i32,
+    range: Range<u64>,
+    /// Lock file created by `with_range()`, removed on drop
+    created: Option<CString>,
+    data: UnsafeCell<*mut u8>,
+}
+
+impl FileLock {
+    /// Opens (creating it if needed) the file at `path` and locks `range` of it. An empty
+    /// range locks the whole file, however large it grows. Locks on different ranges of
+    /// the same file are independent.
+    pub fn open(path: &Path, range: Range<u64>, data: *mut u8) -> Result<Self> {
+        check_range(&range)?;
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| -> Box<dyn std::error::Error> { From::from("Invalid lock file path") })?;
+        let fd = unsafe { Self::open_fd(&path, libc::O_CREAT)? };
+        Ok(Self {
+            fd,
+            range,
+            created: None,
+            data: UnsafeCell::new(data),
+        })
+    }
+
+    unsafe fn open_fd(path: &CStr, flags: i32) -> Result<i32> {
+        let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC | flags, 0o600);
+        trace!("open({:?}) = {}", path, fd);
+        if fd < 0 {
+            return Err(From::from(format!(
+                "Failed to open lock file {:?} : {}",
+                path,
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(fd)
+    }
+
+    /// Creates a new lock file in the temporary directory, stores its path and `range` in `mem`
+    /// and returns the number of used bytes. The file is removed when the returned lock is dropped.
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn with_range(
+        mem: *mut u8,
+        range: Range<u64>,
+        data: *mut u8,
+    ) -> Result<(Self, usize)> {
+        check_align(mem, align_of::<u64>(), "FileLock")?;
+        if range.end < range.start {
+            return Err(From::from("FileLock range ends before it starts"));
+        }
+        check_range(&range)?;
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut id = seed ^ std::process::id().rotate_left(16);
+        let (path, fd) = loop {
+            let path = std::env::temp_dir().join(format!("raw_sync_{}.lock", id));
+            let path = match CString::new(path.as_os_str().as_bytes()) {
+                Ok(p) if p.as_bytes_with_nul().len() <= MAX_PATH => p,
+                _ => return Err(From::from("Temporary directory path is too long")),
+            };
+            let flags = libc::O_RDWR | libc::O_CLOEXEC | libc::O_CREAT | libc::O_EXCL;
+            let fd = libc::open(path.as_ptr(), flags, 0o600);
+            trace!("open({:?}, O_CREAT | O_EXCL) = {}", path, fd);
+            if fd >= 0 {
+                break (path, fd);
+            }
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EEXIST) {
+                return Err(From::from(format!(
+                    "Failed to create lock file {:?} : {}",
+                    path, err
+                )));
+            }
+            id = id.wrapping_add(1);
+        };
+
+        let mut stored = FileLockInfo {
+            start: range.start,
+            len: range.end - range.start,
+            path: [0; MAX_PATH],
+        };
+        stored.path[..path.as_bytes().len()].copy_from_slice(path.as_bytes());
+        (mem as *mut FileLockInfo).write(stored);
+
+        let obj = Self {
+            fd,
+            range,
+            created: Some(path),
+            data: UnsafeCell::new(data),
+        };
+        Ok((obj, size_of::<FileLockInfo>()))
+    }
+
+    /// Opens the lock file whose path and range `with_range()` stored in `mem` and returns
+    /// the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn attach(mem: *mut u8, data: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, align_of::<u64>(), "FileLock")?;
+        let info = &*(mem as *const FileLockInfo);
+        let path = match CStr::from_bytes_until_nul(&info.path) {
+            Ok(p) if !p.to_bytes().is_empty() => p,
+            _ => return Err(From::from("Existing FileLock is corrupted")),
+        };
+        let end = match info.start.checked_add(info.len) {
+            Some(end) => end,
+            None => return Err(From::from("Existing FileLock is corrupted")),
+        };
+        check_range(&(info.start..end))?;
+        let fd = Self::open_fd(path, 0)?;
+
+        let obj = Self {
+            fd,
+            range: info.start..end,
+            created: None,
+            data: UnsafeCell::new(data),
+        };
+        Ok((obj, size_of::<FileLockInfo>()))
+    }
+
+    /// Locked byte range of the file, empty when the whole file is locked
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+
+    /// Runs `cmd` (F_OFD_SETLK or F_OFD_SETLKW) with a lock of `kind` on the range
+    fn fcntl(&self, cmd: i32, kind: i32) -> std::io::Result<()> {
+        let mut lock: flock = unsafe { std::mem::zeroed() };
+        lock.l_type = kind as c_short;
+        lock.l_whence = SEEK_SET as c_short;
+        lock.l_start = self.range.start as off_t;
+        lock.l_len = (self.range.end.saturating_sub(self.range.start)) as off_t;
+        loop {
+            let res = unsafe { libc::fcntl(self.fd, cmd, &lock) };
+            trace!("fcntl({}, {}, {}) = {}", self.fd, cmd, kind, res);
+            if res == 0 {
+                return Ok(());
+            }
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINTR) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Takes a lock of `kind`, polling until `timeout` expires as there is no timed fcntl()
+    fn acquire(&self, kind: i32, timeout: Timeout) -> Result<()> {
+        if let Timeout::Infinite = timeout {
+            return self
+                .fcntl(F_OFD_SETLKW, kind)
+                .map_err(|e| From::from(format!("Failed to acquire file lock : {}", e)));
+        }
+
+        let deadline = Deadline::new(timeout);
+        let mut poll = Duration::from_micros(50);
+        loop {
+            match self.fcntl(F_OFD_SETLK, kind) {
+                Ok(()) => return Ok(()),
+                Err(e)
+                    if e.raw_os_error() == Some(libc::EAGAIN)
+                        || e.raw_os_error() == Some(libc::EACCES) => {}
+                Err(e) => return Err(From::from(format!("Failed to acquire file lock : {}", e))),
+            }
+            let sleep = match deadline.remaining() {
+                Some(Timeout::Val(d)) if !d.is_zero() => std::cmp::min(poll, d),
+                _ => return Err(Box::new(TimeoutError { what: "file lock" })),
+            };
+            std::thread::sleep(sleep);
+            poll = std::cmp::min(poll * 2, MAX_POLL);
+        }
+    }
+}
+
+impl LockInit for FileLock {
+    const SIZE: usize = size_of::<FileLockInfo>();
+    const ALIGN: usize = align_of::<u64>();
+
+    fn size_of(_addr: Option<*mut u8>) -> usize {
+        size_of::<FileLockInfo>()
+    }
+
+    /// Creates a new lock file like `with_range()`, locking the whole file
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        let (obj, used) = Self::with_range(mem, 0..0, data)?;
+        Ok((Box::new(obj), used))
+    }
+
+    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        let (obj, used) = Self::attach(mem, data)?;
+        Ok((Box::new(obj), used))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        trace!("close({})", self.fd);
+        unsafe { libc::close(self.fd) };
+        if let Some(path) = self.created.take() {
+            trace!("unlink({:?})", path);
+            unsafe { libc::unlink(path.as_ptr()) };
+        }
+    }
+}
+
+impl LockImpl for FileLock {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        self.fd as usize as _
+    }
+
+    fn lock(&self) -> Result<LockGuard<'_>> {
+        self.acquire(F_WRLCK, Timeout::Infinite)?;
+        Ok(LockGuard::new(self))
+    }
+
+    fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
+        self.acquire(F_WRLCK, timeout)?;
+        Ok(LockGuard::new(self))
+    }
+
+    fn release(&self) -> Result<()> {
+        self.fcntl(F_OFD_SETLK, F_UNLCK)
+            .map_err(|e| From::from(format!("Failed to release file lock : {}", e)))
+    }
+
+    fn rlock(&self) -> Result<ReadLockGuard<'_>> {
+        self.acquire(F_RDLCK, Timeout::Infinite)?;
+        Ok(ReadLockGuard::new(self))
+    }
+
+    fn try_rlock(&self, timeout: Timeout) -> Result<ReadLockGuard<'_>> {
+        self.acquire(F_RDLCK, timeout)?;
+        Ok(ReadLockGuard::new(self))
+    }
+
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        &mut *self.data.get()
+    }
+}
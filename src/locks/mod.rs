@@ -1,3 +1,6 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
 use std::ops::{Deref, DerefMut};
 
 cfg_if::cfg_if! {
@@ -11,10 +14,78 @@ cfg_if::cfg_if! {
         unimplemented!("This crate does not support your OS yet !");
     }
 }
+use crate::mapped::{self, Mapped, Mapping};
+use crate::named::{self, Named};
 use crate::{Result, Timeout};
 pub use os::*;
 
+#[cfg(target_os = "linux")]
+mod file;
+#[cfg(target_os = "linux")]
+pub use file::*;
+mod poison;
+pub use poison::*;
+mod registry;
+pub use registry::*;
+mod seqlock;
+pub use seqlock::*;
+#[cfg(target_os = "linux")]
+mod sysv;
+#[cfg(target_os = "linux")]
+pub use sysv::*;
+
+thread_local! {
+    static RELEASE_ERROR: RefCell<Option<Box<dyn Error>>> = const { RefCell::new(None) };
+}
+
+/// Returns the error of the last failed release done implicitly by dropping a guard on this thread.
+/// Guards never panic on `Drop`, use `unlock()` to handle release errors directly.
+pub fn take_release_error() -> Option<Box<dyn Error>> {
+    RELEASE_ERROR.with(|e| e.borrow_mut().take())
+}
+
+fn record_release_error(err: Box<dyn Error>) {
+    warn!("Failed to release lock on drop : {}", err);
+    RELEASE_ERROR.with(|e| *e.borrow_mut() = Some(err));
+}
+
+/// Returned when a lock was acquired after its previous owner exited without releasing it.
+/// The lock is released again before this error is returned.
+///
+/// Only `Mutex` reports it, on Windows and on Linux where it is a robust pthread mutex.
+/// Other Unix systems like macOS have no robust mutexes, a dead owner's `Mutex` stays locked.
+#[derive(Debug)]
+pub struct AbandonedError;
+impl fmt::Display for AbandonedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The previous owner of the lock exited without releasing it"
+        )
+    }
+}
+impl Error for AbandonedError {}
+
+/// Returned by `try_lock()` and `try_rlock()` when the lock is still held by another owner once
+/// the timeout expired
+#[derive(Debug)]
+pub struct TimeoutError {
+    /// Kind of lock that was waited for
+    pub what: &'static str,
+}
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timed out waiting for {}", self.what)
+    }
+}
+impl Error for TimeoutError {}
+
 pub trait LockInit {
+    /// Size of the lock's internal representation at an address aligned to `ALIGN`
+    const SIZE: usize;
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    const ALIGN: usize;
+
     /// Size required for the lock's internal representation
     fn size_of(addr: Option<*mut u8>) -> usize;
 
@@ -29,6 +100,86 @@ pub trait LockInit {
     /// This function is unsafe because it cannot guarantee that the provided memory is valid.
     #[allow(clippy::new_ret_no_self)]
     unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)>;
+
+    /// Creates the lock in a new shared memory object called `name`, followed by `data_size`
+    /// bytes of data it protects. The name is removed when the returned value is dropped.
+    fn create_named(name: &str, data_size: usize) -> Result<Named<Box<dyn LockImpl>>>
+    where
+        Self: Sized,
+    {
+        let size = match Self::size_of(None).checked_add(data_size) {
+            Some(size) => size,
+            None => return Err(From::from(format!("Data size {} is too large", data_size))),
+        };
+        named::create(name, size, |mem| unsafe {
+            let data = named::data_after(mem, Self::size_of(Some(mem)));
+            Ok(Self::new(mem, data)?.0)
+        })
+    }
+
+    /// Opens a lock created with `create_named()`
+    fn open_named(name: &str) -> Result<Named<Box<dyn LockImpl>>>
+    where
+        Self: Sized,
+    {
+        named::open(name, |mem| unsafe {
+            let data = named::data_after(mem, Self::size_of(Some(mem)));
+            Ok(Self::from_existing(mem, data)?.0)
+        })
+    }
+
+    /// Initializes the lock `offset` bytes into `map`, followed by `data_size` bytes of data it
+    /// protects. The returned lock borrows `map` so it cannot be used once unmapped.
+    fn create_in<M: Mapping>(
+        map: &M,
+        offset: usize,
+        data_size: usize,
+    ) -> Result<Mapped<'_, Box<dyn LockImpl>>>
+    where
+        Self: Sized,
+    {
+        let mem = mapped::at(map, offset, |mem| unsafe {
+            // An overflowing size cannot fit and is rejected by `mapped::at()`
+            (named::data_after(mem, Self::SIZE) as usize - mem as usize).saturating_add(data_size)
+        })?;
+        let lock = unsafe { Self::new(mem, named::data_after(mem, Self::SIZE))?.0 };
+        Ok(mapped::wrap(map, lock))
+    }
+
+    /// Re-uses a lock created with `create_in()` at the same offset of the same memory
+    fn open_in<M: Mapping>(
+        map: &M,
+        offset: usize,
+        data_size: usize,
+    ) -> Result<Mapped<'_, Box<dyn LockImpl>>>
+    where
+        Self: Sized,
+    {
+        let mem = mapped::at(map, offset, |mem| unsafe {
+            // An overflowing size cannot fit and is rejected by `mapped::at()`
+            (named::data_after(mem, Self::SIZE) as usize - mem as usize).saturating_add(data_size)
+        })?;
+        let lock = unsafe { Self::from_existing(mem, named::data_after(mem, Self::SIZE))?.0 };
+        Ok(mapped::wrap(map, lock))
+    }
+
+    /// Places the lock `offset` bytes into `shmem`, followed by `data_size` bytes of data it
+    /// protects. The owner of `shmem` initializes it and other processes open it.
+    #[cfg(feature = "shared_memory")]
+    fn in_shmem(
+        shmem: &shared_memory::Shmem,
+        offset: usize,
+        data_size: usize,
+    ) -> Result<Mapped<'_, Box<dyn LockImpl>>>
+    where
+        Self: Sized,
+    {
+        if shmem.is_owner() {
+            Self::create_in(shmem, offset, data_size)
+        } else {
+            Self::open_in(shmem, offset, data_size)
+        }
+    }
 }
 
 pub trait LockImpl {
@@ -36,7 +187,7 @@ pub trait LockImpl {
     /// Acquires the lock
     fn lock(&self) -> Result<LockGuard<'_>>;
 
-    /// Acquires lock with timeout
+    /// Acquires lock with timeout. Fails with a `TimeoutError` if it is still held once `timeout` expired
     fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>>;
 
     /// Release the lock
@@ -52,6 +203,46 @@ pub trait LockImpl {
         Ok(self.try_lock(timeout)?.into_read_guard())
     }
 
+    /// Returns true if a previous owner panicked while holding the lock. Only locks wrapped in `Poison` can become poisoned
+    fn is_poisoned(&self) -> bool {
+        false
+    }
+
+    /// Clears the poisoned state of the lock
+    fn clear_poison(&self) {}
+
+    /// Acquires the lock like `lock()`. If it is poisoned the lock stays held and its guard is
+    /// returned in `LockError::Poisoned`, it stays poisoned until `clear_poison()` is called
+    fn lock_checked(&self) -> LockResult<LockGuard<'_>> {
+        Ok(self.lock()?)
+    }
+
+    /// Acquires the lock with timeout like `try_lock()`, a poisoned lock is handled like `lock_checked()`
+    fn try_lock_checked(&self, timeout: Timeout) -> LockResult<LockGuard<'_>> {
+        Ok(self.try_lock(timeout)?)
+    }
+
+    /// Acquires the lock for read access like `rlock()`, a poisoned lock is handled like `lock_checked()`
+    fn rlock_checked(&self) -> LockResult<ReadLockGuard<'_>> {
+        Ok(self.rlock()?)
+    }
+
+    /// Acquires the lock for read access with timeout, a poisoned lock is handled like `lock_checked()`
+    fn try_rlock_checked(&self, timeout: Timeout) -> LockResult<ReadLockGuard<'_>> {
+        Ok(self.try_rlock(timeout)?)
+    }
+
+    /// Returns the OS mutex backing this lock if it is a `Mutex`. Used by `Condvar`, wrappers that
+    /// must see every release like `TrackedLock` return `None`
+    #[doc(hidden)]
+    fn as_mutex(&self) -> Option<*mut std::ffi::c_void> {
+        None
+    }
+
+    /// Marks the lock as poisoned. Called when a `LockGuard` is dropped while panicking
+    #[doc(hidden)]
+    fn poison(&self) {}
+
     /// Leaks the inner data without acquiring the lock
     #[doc(hidden)]
     #[allow(clippy::mut_from_ref)]
@@ -64,13 +255,28 @@ pub struct LockGuard<'t> {
 }
 impl<'t> Drop for LockGuard<'t> {
     fn drop(&mut self) {
-        self.lock.release().unwrap();
+        if std::thread::panicking() {
+            self.lock.poison();
+        }
+        if let Err(e) = self.lock.release() {
+            record_release_error(e);
+        }
     }
 }
 impl<'t> LockGuard<'t> {
-    fn new(lock_impl: &'t dyn LockImpl) -> Self {
+    /// Wraps `lock_impl` once acquired, for `LockImpl` implementations outside this crate
+    pub fn new(lock_impl: &'t dyn LockImpl) -> Self {
         Self { lock: lock_impl }
     }
+    /// Releases the lock, returning any error instead of recording it like `Drop` does
+    pub fn unlock(self) -> Result<()> {
+        let inner_lock = self.lock;
+        std::mem::forget(self);
+        inner_lock.release()
+    }
+    pub(crate) fn lock_impl(&self) -> &'t dyn LockImpl {
+        self.lock
+    }
     pub fn into_read_guard(self) -> ReadLockGuard<'t> {
         let inner_lock = self.lock;
         std::mem::forget(self);
@@ -94,14 +300,23 @@ pub struct ReadLockGuard<'t> {
     lock: &'t dyn LockImpl,
 }
 impl<'t> ReadLockGuard<'t> {
-    fn new(lock_impl: &'t dyn LockImpl) -> Self {
+    /// Wraps `lock_impl` once acquired for reading, for `LockImpl` implementations outside this crate
+    pub fn new(lock_impl: &'t dyn LockImpl) -> Self {
         Self { lock: lock_impl }
     }
+    /// Releases the lock, returning any error instead of recording it like `Drop` does
+    pub fn unlock(self) -> Result<()> {
+        let inner_lock = self.lock;
+        std::mem::forget(self);
+        inner_lock.release()
+    }
 }
 
 impl<'t> Drop for ReadLockGuard<'t> {
     fn drop(&mut self) {
-        self.lock.release().unwrap();
+        if let Err(e) = self.lock.release() {
+            record_release_error(e);
+        }
     }
 }
 impl<'t> Deref for ReadLockGuard<'t> {
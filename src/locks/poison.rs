@@ -0,0 +1,237 @@
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{align_of, forget, size_of};
+
+use super::{LockGuard, LockImpl, LockInit, ReadLockGuard};
+use crate::sync::{AtomicU32, Ordering};
+use crate::{check_align, max_align, Result, Timeout};
+
+/// Returned when acquiring a lock whose previous owner panicked while holding it.
+/// The `*_checked()` acquire methods keep the lock held and hand its guard back here, use
+/// `into_inner()` to access the data anyway and `clear_poison()` once it has been repaired.
+/// The other acquire methods release the lock and return a `PoisonError<()>`.
+pub struct PoisonError<G = ()> {
+    guard: G,
+}
+impl<G> PoisonError<G> {
+    pub fn new(guard: G) -> Self {
+        Self { guard }
+    }
+    /// Returns the guard of the poisoned lock
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Lock is poisoned : a previous owner panicked while holding it"
+        )
+    }
+}
+impl<G> Error for PoisonError<G> {}
+
+/// Error of the `*_checked()` acquire methods
+pub enum LockError<G> {
+    /// The lock was acquired but is poisoned
+    Poisoned(PoisonError<G>),
+    /// The lock could not be acquired
+    Failed(Box<dyn Error>),
+}
+impl<G> LockError<G> {
+    pub(crate) fn map<H>(self, f: impl FnOnce(G) -> H) -> LockError<H> {
+        match self {
+            Self::Poisoned(e) => LockError::Poisoned(PoisonError::new(f(e.into_inner()))),
+            Self::Failed(e) => LockError::Failed(e),
+        }
+    }
+}
+impl<G> fmt::Debug for LockError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Poisoned(e) => f.debug_tuple("Poisoned").field(e).finish(),
+            Self::Failed(e) => f.debug_tuple("Failed").field(e).finish(),
+        }
+    }
+}
+impl<G> From<Box<dyn Error>> for LockError<G> {
+    fn from(e: Box<dyn Error>) -> Self {
+        Self::Failed(e)
+    }
+}
+/// Releases a poisoned lock by dropping its guard
+impl<G> From<LockError<G>> for Box<dyn Error> {
+    fn from(e: LockError<G>) -> Self {
+        match e {
+            LockError::Poisoned(_) => Box::new(PoisonError::new(())),
+            LockError::Failed(e) => e,
+        }
+    }
+}
+
+/// Result of the `*_checked()` acquire methods
+pub type LockResult<G> = std::result::Result<G, LockError<G>>;
+
+/// Returns the guard of a lock even if it is poisoned
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub(crate) fn ignore_poison<G>(res: LockResult<G>) -> Result<G> {
+    match res {
+        Ok(guard) => Ok(guard),
+        Err(LockError::Poisoned(e)) => Ok(e.into_inner()),
+        Err(LockError::Failed(e)) => Err(e),
+    }
+}
+
+/// Adds `std::sync::Mutex` like poisoning to any lock of this crate. The poisoned
+/// flag lives in the shared memory right after the wrapped lock so it is seen by every process.
+pub struct Poison<L> {
+    lock: Box<dyn LockImpl>,
+    poisoned: *const AtomicU32,
+    _kind: PhantomData<L>,
+}
+
+impl<L: LockInit + 'static> Poison<L> {
+    unsafe fn flag_ptr(mem: *mut u8, used_bytes: usize) -> *mut AtomicU32 {
+        let ptr = mem.add(used_bytes);
+        ptr.add(ptr.align_offset(size_of::<AtomicU32>() as _)) as *mut AtomicU32
+    }
+
+    /// Hands `guard` back in a `PoisonError` if the lock is poisoned
+    fn checked<G>(&self, guard: G) -> LockResult<G> {
+        if self.is_poisoned() {
+            return Err(LockError::Poisoned(PoisonError::new(guard)));
+        }
+        Ok(guard)
+    }
+}
+
+impl<L: LockInit + 'static> LockInit for Poison<L> {
+    const SIZE: usize = L::SIZE.next_multiple_of(align_of::<AtomicU32>()) + size_of::<AtomicU32>();
+    const ALIGN: usize = max_align(L::ALIGN, align_of::<AtomicU32>());
+
+    fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::SIZE
+    }
+
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Poison")?;
+        let (lock, used_bytes) = L::new(mem, data)?;
+        let poisoned = Self::flag_ptr(mem, used_bytes);
+        poisoned.write(AtomicU32::new(0));
+
+        let obj = Box::new(Self {
+            lock,
+            poisoned,
+            _kind: PhantomData,
+        });
+        Ok((
+            obj,
+            (poisoned as usize - mem as usize) + size_of::<AtomicU32>(),
+        ))
+    }
+
+    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Poison")?;
+        let (lock, used_bytes) = L::from_existing(mem, data)?;
+        let poisoned = Self::flag_ptr(mem, used_bytes);
+
+        if (*poisoned).load(Ordering::Relaxed) > 1 {
+            return Err(From::from("Existing Poison lock is corrupted"));
+        }
+
+        let obj = Box::new(Self {
+            lock,
+            poisoned,
+            _kind: PhantomData,
+        });
+        Ok((
+            obj,
+            (poisoned as usize - mem as usize) + size_of::<AtomicU32>(),
+        ))
+    }
+}
+
+impl<L: LockInit + 'static> LockImpl for Poison<L> {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        self.lock.as_raw()
+    }
+
+    fn lock(&self) -> Result<LockGuard<'_>> {
+        Ok(self.lock_checked()?)
+    }
+
+    fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
+        Ok(self.try_lock_checked(timeout)?)
+    }
+
+    fn release(&self) -> Result<()> {
+        self.lock.release()
+    }
+
+    fn rlock(&self) -> Result<ReadLockGuard<'_>> {
+        Ok(self.rlock_checked()?)
+    }
+
+    fn try_rlock(&self, timeout: Timeout) -> Result<ReadLockGuard<'_>> {
+        Ok(self.try_rlock_checked(timeout)?)
+    }
+
+    fn lock_checked(&self) -> LockResult<LockGuard<'_>> {
+        forget(self.lock.lock()?);
+        self.checked(LockGuard::new(self))
+    }
+
+    fn try_lock_checked(&self, timeout: Timeout) -> LockResult<LockGuard<'_>> {
+        forget(self.lock.try_lock(timeout)?);
+        self.checked(LockGuard::new(self))
+    }
+
+    fn rlock_checked(&self) -> LockResult<ReadLockGuard<'_>> {
+        forget(self.lock.rlock()?);
+        self.checked(ReadLockGuard::new(self))
+    }
+
+    fn try_rlock_checked(&self, timeout: Timeout) -> LockResult<ReadLockGuard<'_>> {
+        forget(self.lock.try_rlock(timeout)?);
+        self.checked(ReadLockGuard::new(self))
+    }
+
+    fn is_poisoned(&self) -> bool {
+        unsafe { &*self.poisoned }.load(Ordering::Relaxed) != 0
+    }
+
+    fn clear_poison(&self) {
+        unsafe { &*self.poisoned }.store(0, Ordering::Relaxed);
+    }
+
+    fn as_mutex(&self) -> Option<*mut std::ffi::c_void> {
+        self.lock.as_mutex()
+    }
+
+    fn poison(&self) {
+        warn!("Poisoning lock {:p}", self.lock.as_raw());
+        unsafe { &*self.poisoned }.store(1, Ordering::Relaxed);
+    }
+
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        self.lock.get_inner()
+    }
+}
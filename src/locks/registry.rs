@@ -0,0 +1,456 @@
+use std::cell::Cell;
+use std::fmt;
+use std::mem::{forget, size_of};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use super::{
+    LockError, LockGuard, LockImpl, LockInit, LockResult, Mutex, PoisonError, ReadLockGuard,
+    TimeoutError,
+};
+use crate::{check_align, max_align, Result, Timeout};
+
+/// Offset of the slots, after the mutex
+const SLOTS: usize = Mutex::SIZE.next_multiple_of(size_of::<*mut u8>());
+
+/// Maximum number of (lock, owner) pairs a registry can track at once
+pub const REGISTRY_MAX_HOLDS: usize = 256;
+/// Maximum number of owners that can be blocked on a tracked lock at once
+pub const REGISTRY_MAX_WAITS: usize = 64;
+
+/// Process-local counter, loom atomics cannot be used in statics
+static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
+thread_local! {
+    static THREAD_ID: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Identifies the current thread across every process using the registry
+fn current_owner() -> u64 {
+    let tid = THREAD_ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    });
+    ((std::process::id() as u64) << 32) | tid as u64
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Slot {
+    /// 0 when the slot is free
+    owner: u64,
+    lock_id: u64,
+}
+
+#[repr(C)]
+struct InnerRegistry {
+    holds: [Slot; REGISTRY_MAX_HOLDS],
+    waits: [Slot; REGISTRY_MAX_WAITS],
+}
+
+impl InnerRegistry {
+    fn waiting_on(&self, owner: u64) -> Option<u64> {
+        self.waits
+            .iter()
+            .find(|s| s.owner == owner)
+            .map(|s| s.lock_id)
+    }
+
+    /// Follows the wait-for graph from `owner` blocking on `lock_id` and returns the
+    /// edges of the cycle leading back to `owner` if there is one
+    fn find_cycle(&self, owner: u64, lock_id: u64) -> Option<Vec<WaitEdge>> {
+        let mut path = vec![WaitEdge::new(owner, lock_id)];
+        if self.walk(owner, lock_id, &mut path) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    fn walk(&self, target: u64, lock_id: u64, path: &mut Vec<WaitEdge>) -> bool {
+        for holder in self
+            .holds
+            .iter()
+            .filter(|s| s.owner != 0 && s.lock_id == lock_id)
+            .map(|s| s.owner)
+        {
+            if holder == target {
+                return true;
+            }
+            // Owners already on the path are part of another cycle
+            if path.iter().any(|e| e.owner() == holder) {
+                continue;
+            }
+            if let Some(next_lock) = self.waiting_on(holder) {
+                path.push(WaitEdge::new(holder, next_lock));
+                if self.walk(target, next_lock, path) {
+                    return true;
+                }
+                path.pop();
+            }
+        }
+        false
+    }
+}
+
+/// An owner (thread of a process) blocked while trying to acquire a lock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitEdge {
+    /// Process id of the waiting owner
+    pub pid: u32,
+    /// Registry specific id of the waiting thread within its process
+    pub thread: u32,
+    /// Id of the lock being waited on
+    pub lock_id: u64,
+}
+impl WaitEdge {
+    fn new(owner: u64, lock_id: u64) -> Self {
+        Self {
+            pid: (owner >> 32) as u32,
+            thread: owner as u32,
+            lock_id,
+        }
+    }
+    fn owner(&self) -> u64 {
+        ((self.pid as u64) << 32) | self.thread as u64
+    }
+}
+
+/// A cycle in the wait-for graph. Each owner waits on a lock held by the next one
+/// and the last owner waits on a lock held by the first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deadlock {
+    pub edges: Vec<WaitEdge>,
+}
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.edges.iter().enumerate() {
+            if i != 0 {
+                write!(f, " -> ")?;
+            }
+            write!(
+                f,
+                "[pid {} thread {}] waits on lock {}",
+                e.pid, e.thread, e.lock_id
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned by a tracked lock when blocking would complete a cycle in the wait-for graph
+#[derive(Debug)]
+pub struct DeadlockError {
+    pub deadlock: Deadlock,
+}
+impl fmt::Display for DeadlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Deadlock detected : {}", self.deadlock)
+    }
+}
+impl std::error::Error for DeadlockError {}
+
+/// Records which owners hold and wait on tracked locks in order to detect deadlocks
+/// across processes. The registry lives in shared memory like any other primitive.
+pub struct LockRegistry {
+    mutex: Box<dyn LockImpl>,
+    inner: *mut InnerRegistry,
+}
+
+impl LockRegistry {
+    /// Size of the registry's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize = SLOTS + size_of::<InnerRegistry>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(Mutex::ALIGN, size_of::<*mut u8>());
+
+    /// Size required for the registry's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::SIZE
+    }
+
+    /// Initializes a new registry in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "LockRegistry")?;
+        let (mutex, _) = Mutex::new(mem, null_mut())?;
+        let ptr = mem.add(SLOTS) as *mut InnerRegistry;
+        let empty = Slot {
+            owner: 0,
+            lock_id: 0,
+        };
+        ptr.write(InnerRegistry {
+            holds: [empty; REGISTRY_MAX_HOLDS],
+            waits: [empty; REGISTRY_MAX_WAITS],
+        });
+
+        let obj = Self { mutex, inner: ptr };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Re-uses a registry from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "LockRegistry")?;
+        let (mutex, _) = Mutex::from_existing(mem, null_mut())?;
+        let ptr = mem.add(SLOTS) as *mut InnerRegistry;
+
+        let obj = Self { mutex, inner: ptr };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Wraps a lock so that its acquisitions are recorded in this registry.
+    /// Every process must use the same `lock_id` for the same underlying lock.
+    pub fn track<'r>(&'r self, lock_id: u64, lock: Box<dyn LockImpl + 'r>) -> TrackedLock<'r> {
+        TrackedLock {
+            registry: self,
+            lock_id,
+            lock,
+        }
+    }
+
+    /// Lists every cycle currently present in the wait-for graph
+    pub fn find_deadlocks(&self) -> Result<Vec<Deadlock>> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &*self.inner };
+        let mut found: Vec<Deadlock> = Vec::new();
+        for w in inner.waits.iter().filter(|s| s.owner != 0) {
+            let edges = match inner.find_cycle(w.owner, w.lock_id) {
+                Some(e) => e,
+                None => continue,
+            };
+            // The same cycle is found once from each of its members
+            let mut owners: Vec<u64> = edges.iter().map(|e| e.owner()).collect();
+            owners.sort_unstable();
+            if found.iter().any(|d| {
+                let mut o: Vec<u64> = d.edges.iter().map(|e| e.owner()).collect();
+                o.sort_unstable();
+                o == owners
+            }) {
+                continue;
+            }
+            found.push(Deadlock { edges });
+        }
+        drop(guard);
+        Ok(found)
+    }
+
+    /// Removes every entry belonging to `pid`. Use this to clean up after a process that
+    /// exited while holding or waiting on tracked locks.
+    pub fn purge_process(&self, pid: u32) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        for s in inner
+            .holds
+            .iter_mut()
+            .chain(inner.waits.iter_mut())
+            .filter(|s| s.owner != 0 && (s.owner >> 32) as u32 == pid)
+        {
+            s.owner = 0;
+        }
+        drop(guard);
+        Ok(())
+    }
+
+    /// Records `owner` as blocked on `lock_id` unless doing so completes a cycle
+    fn begin_wait(&self, owner: u64, lock_id: u64) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+
+        if let Some(edges) = inner.find_cycle(owner, lock_id) {
+            drop(guard);
+            let deadlock = Deadlock { edges };
+            warn!("Deadlock detected : {}", deadlock);
+            return Err(Box::new(DeadlockError { deadlock }));
+        }
+        let res = match inner.waits.iter_mut().find(|s| s.owner == 0) {
+            Some(slot) => {
+                *slot = Slot { owner, lock_id };
+                Ok(())
+            }
+            None => Err(From::from(
+                "LockRegistry has no free wait slots".to_string(),
+            )),
+        };
+        drop(guard);
+        res
+    }
+
+    fn end_wait(&self, owner: u64) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        if let Some(slot) = inner.waits.iter_mut().find(|s| s.owner == owner) {
+            slot.owner = 0;
+        }
+        drop(guard);
+        Ok(())
+    }
+
+    fn add_hold(&self, owner: u64, lock_id: u64) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        let res = match inner.holds.iter_mut().find(|s| s.owner == 0) {
+            Some(slot) => {
+                *slot = Slot { owner, lock_id };
+                Ok(())
+            }
+            None => Err(From::from(
+                "LockRegistry has no free hold slots".to_string(),
+            )),
+        };
+        drop(guard);
+        res
+    }
+
+    fn remove_hold(&self, owner: u64, lock_id: u64) -> Result<()> {
+        let guard = self.mutex.lock()?;
+        let inner = unsafe { &mut *self.inner };
+        if let Some(slot) = inner
+            .holds
+            .iter_mut()
+            .find(|s| s.owner == owner && s.lock_id == lock_id)
+        {
+            slot.owner = 0;
+        }
+        drop(guard);
+        Ok(())
+    }
+}
+
+/// A lock whose acquisitions are recorded in a `LockRegistry`. Blocking calls that
+/// would deadlock fail with a `DeadlockError` instead of hanging forever.
+/// It cannot be used with a `Condvar`, whose waits release the lock without the registry knowing.
+pub struct TrackedLock<'r> {
+    registry: &'r LockRegistry,
+    lock_id: u64,
+    lock: Box<dyn LockImpl + 'r>,
+}
+
+impl<'r> TrackedLock<'r> {
+    /// Runs `attempt` with a zero timeout first and only records a wait edge if it timed out
+    fn acquire<G>(
+        &self,
+        attempt: impl Fn(Timeout) -> LockResult<G>,
+        blocking: impl FnOnce() -> LockResult<G>,
+    ) -> LockResult<()> {
+        let owner = current_owner();
+        let res = match attempt(Timeout::Val(Duration::from_secs(0))) {
+            Err(LockError::Failed(e)) if e.is::<TimeoutError>() => {
+                self.registry.begin_wait(owner, self.lock_id)?;
+                let res = blocking();
+                self.registry.end_wait(owner)?;
+                res
+            }
+            res => res,
+        };
+        // The guard is re-created around self so releases go through the registry
+        let poisoned = match res {
+            Ok(g) => {
+                forget(g);
+                false
+            }
+            Err(LockError::Poisoned(e)) => {
+                forget(e.into_inner());
+                true
+            }
+            Err(LockError::Failed(e)) => return Err(e.into()),
+        };
+
+        if let Err(e) = self.registry.add_hold(owner, self.lock_id) {
+            self.lock.release()?;
+            return Err(e.into());
+        }
+        if poisoned {
+            return Err(LockError::Poisoned(PoisonError::new(())));
+        }
+        Ok(())
+    }
+}
+
+impl<'r> LockImpl for TrackedLock<'r> {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        self.lock.as_raw()
+    }
+
+    fn lock(&self) -> Result<LockGuard<'_>> {
+        Ok(self.lock_checked()?)
+    }
+
+    fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
+        Ok(self.try_lock_checked(timeout)?)
+    }
+
+    fn release(&self) -> Result<()> {
+        // The lock is released even if the registry cannot be updated, or it would stay held forever
+        let removed = self.registry.remove_hold(current_owner(), self.lock_id);
+        self.lock.release()?;
+        removed
+    }
+
+    fn rlock(&self) -> Result<ReadLockGuard<'_>> {
+        Ok(self.rlock_checked()?)
+    }
+
+    fn try_rlock(&self, timeout: Timeout) -> Result<ReadLockGuard<'_>> {
+        Ok(self.try_rlock_checked(timeout)?)
+    }
+
+    fn lock_checked(&self) -> LockResult<LockGuard<'_>> {
+        self.acquire(
+            |t| self.lock.try_lock_checked(t),
+            || self.lock.lock_checked(),
+        )
+        .map(|()| LockGuard::new(self))
+        .map_err(|e| e.map(|()| LockGuard::new(self)))
+    }
+
+    fn try_lock_checked(&self, timeout: Timeout) -> LockResult<LockGuard<'_>> {
+        self.acquire(
+            |t| self.lock.try_lock_checked(t),
+            || self.lock.try_lock_checked(timeout),
+        )
+        .map(|()| LockGuard::new(self))
+        .map_err(|e| e.map(|()| LockGuard::new(self)))
+    }
+
+    fn rlock_checked(&self) -> LockResult<ReadLockGuard<'_>> {
+        self.acquire(
+            |t| self.lock.try_rlock_checked(t),
+            || self.lock.rlock_checked(),
+        )
+        .map(|()| ReadLockGuard::new(self))
+        .map_err(|e| e.map(|()| ReadLockGuard::new(self)))
+    }
+
+    fn try_rlock_checked(&self, timeout: Timeout) -> LockResult<ReadLockGuard<'_>> {
+        self.acquire(
+            |t| self.lock.try_rlock_checked(t),
+            || self.lock.try_rlock_checked(timeout),
+        )
+        .map(|()| ReadLockGuard::new(self))
+        .map_err(|e| e.map(|()| ReadLockGuard::new(self)))
+    }
+
+    fn is_poisoned(&self) -> bool {
+        self.lock.is_poisoned()
+    }
+
+    fn clear_poison(&self) {
+        self.lock.clear_poison()
+    }
+
+    fn poison(&self) {
+        self.lock.poison()
+    }
+
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        self.lock.get_inner()
+    }
+}
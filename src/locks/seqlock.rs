@@ -0,0 +1,171 @@
+use std::mem::{align_of, forget, size_of};
+use std::time::Instant;
+
+use super::{LockGuard, LockImpl, LockInit, Mutex, ReadLockGuard};
+use crate::sync::{fence, spin_loop, AtomicU32, Ordering};
+use crate::{check_align, max_align, Result, Timeout};
+
+/// Offset of the sequence, after the mutex
+const SEQ: usize = Mutex::SIZE.next_multiple_of(align_of::<AtomicU32>());
+
+/// Writer side of a `SeqLock`, its guard marks the sequence as odd while the data is modified
+struct Writer {
+    lock: Box<dyn LockImpl>,
+    seq: *const AtomicU32,
+}
+
+impl Writer {
+    fn seq(&self) -> &AtomicU32 {
+        unsafe { &*self.seq }
+    }
+
+    fn begin(&self) {
+        let seq = self.seq().load(Ordering::Relaxed);
+        // An odd sequence means a previous writer died mid-write and the robust Mutex let this
+        // one in (Linux only, it stays locked elsewhere). Readers keep retrying until this write ends
+        if seq & 1 == 0 {
+            self.seq().store(seq.wrapping_add(1), Ordering::Relaxed);
+        }
+        fence(Ordering::Release);
+    }
+
+    fn end(&self) {
+        let seq = self.seq().load(Ordering::Relaxed);
+        self.seq().store(seq.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl LockImpl for Writer {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        self.lock.as_raw()
+    }
+
+    fn lock(&self) -> Result<LockGuard<'_>> {
+        forget(self.lock.lock()?);
+        self.begin();
+        Ok(LockGuard::new(self))
+    }
+
+    fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
+        forget(self.lock.try_lock(timeout)?);
+        self.begin();
+        Ok(LockGuard::new(self))
+    }
+
+    fn release(&self) -> Result<()> {
+        self.end();
+        self.lock.release()
+    }
+
+    fn rlock(&self) -> Result<ReadLockGuard<'_>> {
+        Err(From::from("SeqLock readers must use read()"))
+    }
+
+    fn try_rlock(&self, _timeout: Timeout) -> Result<ReadLockGuard<'_>> {
+        Err(From::from("SeqLock readers must use read()"))
+    }
+
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        self.lock.get_inner()
+    }
+}
+
+/// Sequence lock : writers are serialized by a `Mutex` and bump a sequence counter around
+/// their modifications, readers copy the data and retry if the sequence changed meanwhile.
+/// Readers never write to the shared memory so they do not bounce its cache lines between processes.
+///
+/// The data may be modified while a reader's closure runs, it must only copy it out
+/// (`std::ptr::read_volatile`) and validate it once `read()` returned.
+pub struct SeqLock {
+    writer: Writer,
+}
+
+impl SeqLock {
+    /// Size of the lock's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize = SEQ + size_of::<AtomicU32>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = max_align(Mutex::ALIGN, align_of::<AtomicU32>());
+
+    /// Size required for the lock's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::SIZE
+    }
+
+    /// Initializes a new seqlock protecting `data` and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "SeqLock")?;
+        let (lock, _) = Mutex::new(mem, data)?;
+        let seq = mem.add(SEQ) as *mut AtomicU32;
+        seq.write(AtomicU32::new(0));
+
+        let obj = Self {
+            writer: Writer { lock, seq },
+        };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Re-uses a seqlock from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "SeqLock")?;
+        let (lock, _) = Mutex::from_existing(mem, data)?;
+        let seq = mem.add(SEQ) as *const AtomicU32;
+
+        let obj = Self {
+            writer: Writer { lock, seq },
+        };
+        Ok((obj, Self::SIZE))
+    }
+
+    /// Current value of the sequence counter, odd while a write is in progress
+    pub fn sequence(&self) -> u32 {
+        self.writer.seq().load(Ordering::Acquire)
+    }
+
+    /// Acquires the writer lock. Readers retry until the returned guard is dropped.
+    /// Returns `AbandonedError` once if the previous writer died mid-write, the next write repairs the sequence
+    pub fn write(&self) -> Result<LockGuard<'_>> {
+        self.writer.lock()
+    }
+
+    /// Acquires the writer lock, giving up after `timeout`
+    pub fn try_write(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
+        self.writer.try_lock(timeout)
+    }
+
+    /// Calls `f` with the data until it ran without a concurrent write and returns its result
+    pub fn read<T, F: FnMut(*const u8) -> T>(&self, f: F) -> T {
+        self.try_read(Timeout::Infinite, f)
+            .expect("Reads without a timeout never fail")
+    }
+
+    /// Like `read()` but gives up if no consistent read happened before `timeout`,
+    /// which can only happen when writers hold the lock for too long or die mid-write
+    pub fn try_read<T, F: FnMut(*const u8) -> T>(&self, timeout: Timeout, mut f: F) -> Result<T> {
+        let data = unsafe { *self.writer.get_inner() } as *const u8;
+        let start = Instant::now();
+        loop {
+            let before = self.writer.seq().load(Ordering::Acquire);
+            if before & 1 == 0 {
+                let res = f(data);
+                fence(Ordering::Acquire);
+                if self.writer.seq().load(Ordering::Relaxed) == before {
+                    return Ok(res);
+                }
+            }
+            if let Timeout::Val(d) = timeout {
+                if start.elapsed() >= d {
+                    return Err(From::from("Timed out waiting for a consistent read"));
+                }
+            }
+            spin_loop();
+        }
+    }
+}
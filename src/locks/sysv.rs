@@ -0,0 +1,254 @@
+use std::cell::UnsafeCell;
+use std::mem::{align_of, size_of};
+
+use libc::{
+    c_int, key_t, sembuf, size_t, timespec, GETVAL, IPC_CREAT, IPC_EXCL, IPC_RMID, SEM_UNDO, SETVAL,
+};
+
+use super::{LockGuard, LockImpl, LockInit, TimeoutError};
+use crate::{check_align, Deadline, Result, Timeout};
+
+extern "C" {
+    // Not bound by libc but exported by both glibc and musl
+    fn semtimedop(
+        semid: c_int,
+        sops: *mut sembuf,
+        nsops: size_t,
+        timeout: *const timespec,
+    ) -> c_int;
+}
+
+/// Largest value of a System V semaphore (`SEMVMX`)
+const MAX_VALUE: u32 = 32767;
+
+/// Counting semaphore on a System V semaphore (`semget`), only its key is stored in shared memory.
+///
+/// Every operation uses `SEM_UNDO` so the kernel reverts the changes made by a process when it
+/// dies : permits acquired by a crashed process are given back. In turn, permits must be
+/// released by the process that acquired them.
+///
+/// As a `LockImpl`, the semaphore starts with a single permit and behaves like a mutex.
+pub struct SysVSemaphore {
+    key: key_t,
+    id: c_int,
+    owner: bool,
+    data: UnsafeCell<*mut u8>,
+}
+
+impl SysVSemaphore {
+    unsafe fn key_ptr(mem: *mut u8) -> *mut key_t {
+        mem.add(mem.align_offset(size_of::<key_t>() as _)) as *mut key_t
+    }
+
+    /// Size required for the semaphore's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(size_of::<key_t>() as _),
+            None => 0,
+        };
+        padding + size_of::<key_t>()
+    }
+
+    /// Creates a new semaphore holding `permits` permits and returns the number of used bytes.
+    /// The semaphore is removed from the system when the returned object is dropped.
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn with_permits(mem: *mut u8, permits: u32, data: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, align_of::<key_t>(), "SysVSemaphore")?;
+        if permits > MAX_VALUE {
+            return Err(From::from(format!(
+                "Semaphore cannot hold more than {} permits",
+                MAX_VALUE
+            )));
+        }
+        let ptr = Self::key_ptr(mem);
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut key = (seed ^ std::process::id().rotate_left(16)) as key_t;
+        let id = loop {
+            // Zero is IPC_PRIVATE, which other processes cannot look up
+            if key == 0 {
+                key = 1;
+            }
+            let id = libc::semget(key, 1, IPC_CREAT | IPC_EXCL | 0o600);
+            trace!("semget(0x{:X}, IPC_CREAT | IPC_EXCL) = {}", key, id);
+            if id >= 0 {
+                break id;
+            }
+            if std::io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST) {
+                return Err(From::from(format!(
+                    "Failed to create semaphore : {}",
+                    std::io::Error::last_os_error()
+                )));
+            }
+            key = key.wrapping_add(1);
+        };
+
+        let obj = Self {
+            key,
+            id,
+            owner: true,
+            data: UnsafeCell::new(data),
+        };
+        if libc::semctl(id, 0, SETVAL, permits as c_int) != 0 {
+            return Err(From::from(format!(
+                "Failed to initialize semaphore : {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        ptr.write(key);
+        Ok((obj, (ptr as usize - mem as usize) + size_of::<key_t>()))
+    }
+
+    /// Re-attaches to the semaphore whose key is stored in `mem` and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn attach(mem: *mut u8, data: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, align_of::<key_t>(), "SysVSemaphore")?;
+        let ptr = Self::key_ptr(mem);
+        let key = *ptr;
+        let id = libc::semget(key, 1, 0);
+        trace!("semget(0x{:X}) = {}", key, id);
+        if id < 0 {
+            return Err(From::from(format!(
+                "Failed to open semaphore 0x{:X} : {}",
+                key,
+                std::io::Error::last_os_error()
+            )));
+        }
+
+        let obj = Self {
+            key,
+            id,
+            owner: false,
+            data: UnsafeCell::new(data),
+        };
+        Ok((obj, (ptr as usize - mem as usize) + size_of::<key_t>()))
+    }
+
+    /// Key of the semaphore, as passed to `semget()`
+    pub fn key(&self) -> key_t {
+        self.key
+    }
+
+    /// Number of permits currently available
+    pub fn permits(&self) -> Result<u32> {
+        let res = unsafe { libc::semctl(self.id, 0, GETVAL) };
+        if res < 0 {
+            return Err(From::from(format!(
+                "Failed to read semaphore : {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(res as u32)
+    }
+
+    /// Takes `n` permits at once, waiting until enough are available
+    pub fn acquire(&self, n: u16, timeout: Timeout) -> Result<()> {
+        self.semop(-(n as i32), timeout)
+    }
+
+    /// Gives back `n` permits
+    pub fn release_permits(&self, n: u16) -> Result<()> {
+        self.semop(n as i32, Timeout::Infinite)
+    }
+
+    fn semop(&self, delta: i32, timeout: Timeout) -> Result<()> {
+        // A zero sem_op would wait for the semaphore to reach zero instead
+        if delta == 0 {
+            return Ok(());
+        }
+        if delta < i16::MIN as i32 || delta > i16::MAX as i32 {
+            return Err(From::from(format!(
+                "Semaphore cannot move more than {} permits at once",
+                i16::MAX
+            )));
+        }
+        let mut op = sembuf {
+            sem_num: 0,
+            sem_op: delta as i16,
+            sem_flg: SEM_UNDO as i16,
+        };
+
+        let deadline = Deadline::new(timeout);
+        loop {
+            let res = match deadline.remaining() {
+                Some(Timeout::Infinite) => unsafe { libc::semop(self.id, &mut op, 1) },
+                Some(Timeout::Val(d)) => {
+                    // semtimedop() takes a relative timeout
+                    let ts = timespec {
+                        tv_sec: d.as_secs() as _,
+                        tv_nsec: d.subsec_nanos() as _,
+                    };
+                    unsafe { semtimedop(self.id, &mut op, 1, &ts) }
+                }
+                None => return Err(Box::new(TimeoutError { what: "semaphore" })),
+            };
+            trace!("semop({}, {}) = {}", self.id, delta, res);
+            if res == 0 {
+                return Ok(());
+            }
+            let err = std::io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(libc::EAGAIN) => return Err(Box::new(TimeoutError { what: "semaphore" })),
+                _ => return Err(From::from(format!("Failed to update semaphore : {}", err))),
+            }
+        }
+    }
+}
+
+impl LockInit for SysVSemaphore {
+    const SIZE: usize = size_of::<key_t>();
+    const ALIGN: usize = align_of::<key_t>();
+
+    fn size_of(addr: Option<*mut u8>) -> usize {
+        Self::size_of(addr)
+    }
+
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        let (obj, used) = Self::with_permits(mem, 1, data)?;
+        Ok((Box::new(obj), used))
+    }
+
+    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        let (obj, used) = Self::attach(mem, data)?;
+        Ok((Box::new(obj), used))
+    }
+}
+
+impl Drop for SysVSemaphore {
+    fn drop(&mut self) {
+        if self.owner {
+            trace!("semctl({}, IPC_RMID)", self.id);
+            unsafe { libc::semctl(self.id, 0, IPC_RMID) };
+        }
+    }
+}
+
+impl LockImpl for SysVSemaphore {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        self.id as usize as _
+    }
+
+    fn lock(&self) -> Result<LockGuard<'_>> {
+        self.acquire(1, Timeout::Infinite)?;
+        Ok(LockGuard::new(self))
+    }
+
+    fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
+        self.acquire(1, timeout)?;
+        Ok(LockGuard::new(self))
+    }
+
+    fn release(&self) -> Result<()> {
+        self.release_permits(1)
+    }
+
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        &mut *self.data.get()
+    }
+}
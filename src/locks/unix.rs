@@ -1,5 +1,6 @@
 use std::cell::UnsafeCell;
-use std::mem::{size_of, MaybeUninit};
+use std::ffi::CString;
+use std::mem::{align_of, size_of, MaybeUninit};
 use std::time::Duration;
 
 use libc::{
@@ -26,12 +27,21 @@ use libc::{
     pthread_rwlockattr_init,
     pthread_rwlockattr_setpshared,
     pthread_rwlockattr_t,
+    //Semaphore defs
+    sem_close,
+    sem_open,
+    sem_post,
+    sem_t,
+    sem_unlink,
+    sem_wait,
     timespec,
     CLOCK_REALTIME,
 
+    O_CREAT,
+    O_EXCL,
     PTHREAD_PROCESS_SHARED,
+    SEM_FAILED,
 };
-//use log::*;
 
 extern "C" {
     fn pthread_rwlock_timedrdlock(attr: *mut pthread_rwlock_t, host: *const timespec) -> i32;
@@ -72,56 +82,200 @@ cfg_if::cfg_if! {
    }
 }
 
-use super::{LockGuard, LockImpl, LockInit, ReadLockGuard};
-use crate::{Result, Timeout};
+use super::{AbandonedError, LockGuard, LockImpl, LockInit, ReadLockGuard, TimeoutError};
+use crate::{check_align, Result, Timeout};
 
 /// Adds a duration to the current time
 pub(crate) fn abs_timespec_from_duration(d: Duration) -> timespec {
     unsafe {
-        #[allow(clippy::uninit_assumed_init)]
-        let mut cur_time: timespec = MaybeUninit::uninit().assume_init();
+        let mut cur_time = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
         // Get current time
         clock_gettime(CLOCK_REALTIME, &mut cur_time);
         // Add duration
         cur_time.tv_sec += d.as_secs() as nix::sys::time::time_t;
         cur_time.tv_nsec += d.subsec_nanos() as nix::sys::time::time_t;
+        // tv_nsec must stay below one second or the timed calls fail with EINVAL
+        if cur_time.tv_nsec >= 1_000_000_000 {
+            cur_time.tv_sec += 1;
+            cur_time.tv_nsec -= 1_000_000_000;
+        }
         cur_time
     }
 }
 
+/// Alignment of the `AbiTag` and of the object following it, the same for every pointer width
+pub(crate) const TAG_ALIGN: usize = 8;
+
+/// Kind of pthread object following an `AbiTag`
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum AbiKind {
+    Mutex = 1,
+    RwLock = 2,
+    Cond = 3,
+}
+/// Name of the pthread type of an `AbiKind` read from shared memory
+fn os_type(kind: u8) -> &'static str {
+    match kind {
+        1 => "pthread_mutex_t",
+        2 => "pthread_rwlock_t",
+        3 => "pthread_cond_t",
+        _ => "unknown object",
+    }
+}
+
+/// Written before every pthread object so processes of another ABI (pointer width, byte
+/// order or C library) refuse to open it instead of misreading it. Every field is a byte
+/// or stored little endian so any process can decode it.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AbiTag {
+    kind: u8,
+    pointer_width: u8,
+    big_endian: u8,
+    libc: u8,
+    os_size: [u8; 4],
+}
+
+impl AbiTag {
+    fn current(kind: AbiKind, os_size: usize) -> Self {
+        let libc = if cfg!(target_env = "gnu") {
+            1
+        } else if cfg!(target_env = "musl") {
+            2
+        } else if cfg!(target_vendor = "apple") {
+            3
+        } else {
+            0
+        };
+        Self {
+            kind: kind as u8,
+            pointer_width: usize::BITS as u8,
+            big_endian: cfg!(target_endian = "big") as u8,
+            libc,
+            os_size: (os_size as u32).to_le_bytes(),
+        }
+    }
+
+    /// Space used by a tagged object of `os_size` bytes at an address aligned to `TAG_ALIGN`
+    pub(crate) const fn tagged_size(os_size: usize) -> usize {
+        size_of::<Self>() + os_size
+    }
+
+    /// Space used by a tagged object of `os_size` bytes at `addr`
+    pub(crate) fn size_of(addr: Option<*mut u8>, os_size: usize) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(TAG_ALIGN),
+            None => 0,
+        };
+        padding + size_of::<Self>() + os_size
+    }
+
+    /// Returns the tag and object addresses for `mem`
+    unsafe fn locate(mem: *mut u8) -> (*mut Self, *mut u8) {
+        let tag = mem.add(mem.align_offset(TAG_ALIGN)) as *mut Self;
+        (tag, tag.add(1) as *mut u8)
+    }
+
+    /// Writes the tag describing this process and returns the object's address
+    pub(crate) unsafe fn write(mem: *mut u8, kind: AbiKind, os_size: usize) -> *mut u8 {
+        let (tag, obj) = Self::locate(mem);
+        tag.write(Self::current(kind, os_size));
+        obj
+    }
+
+    /// Returns the object's address if the tag was written by a process of the same ABI
+    pub(crate) unsafe fn check(mem: *mut u8, kind: AbiKind, os_size: usize) -> Result<*mut u8> {
+        let (tag, obj) = Self::locate(mem);
+        let found = tag.read();
+        let expected = Self::current(kind, os_size);
+        if found.kind != expected.kind {
+            return Err(From::from(format!(
+                "Existing memory does not hold an initialized {}",
+                os_type(expected.kind)
+            )));
+        }
+        if found != expected {
+            return Err(From::from(format!(
+                "{} was created by a {} process, this process is {}",
+                os_type(expected.kind),
+                found,
+                expected
+            )));
+        }
+        Ok(obj)
+    }
+}
+
+impl std::fmt::Display for AbiTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let libc = match self.libc {
+            1 => "glibc",
+            2 => "musl",
+            3 => "Apple libc",
+            _ => "unknown libc",
+        };
+        write!(
+            f,
+            "{}-bit {} endian {} ({} byte {})",
+            self.pointer_width,
+            if self.big_endian != 0 {
+                "big"
+            } else {
+                "little"
+            },
+            libc,
+            u32::from_le_bytes(self.os_size),
+            os_type(self.kind)
+        )
+    }
+}
+
 pub struct Mutex {
     ptr: *mut pthread_mutex_t,
     data: UnsafeCell<*mut u8>,
 }
 
 impl LockInit for Mutex {
+    const SIZE: usize = AbiTag::tagged_size(size_of::<pthread_mutex_t>());
+    const ALIGN: usize = TAG_ALIGN;
+
     fn size_of(addr: Option<*mut u8>) -> usize {
-        let padding = match addr {
-            Some(mem) => mem.align_offset(size_of::<*mut u8>() as _),
-            None => 0,
-        };
-        padding + size_of::<pthread_mutex_t>()
+        AbiTag::size_of(addr, size_of::<pthread_mutex_t>())
     }
 
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
-        let padding = mem.align_offset(size_of::<*mut u8>() as _);
-        #[allow(clippy::uninit_assumed_init)]
-        let mut lock_attr: pthread_mutexattr_t = MaybeUninit::uninit().assume_init();
-        //trace!("pthread_mutexattr_init");
-        if pthread_mutexattr_init(&mut lock_attr) != 0 {
+        check_align(mem, Self::ALIGN, "Mutex")?;
+        let mut lock_attr = MaybeUninit::<pthread_mutexattr_t>::uninit();
+        trace!("pthread_mutexattr_init");
+        if pthread_mutexattr_init(lock_attr.as_mut_ptr()) != 0 {
             return Err(From::from(
                 "Failed to initialize pthread_mutexattr_t".to_string(),
             ));
         }
-        //trace!("pthread_mutexattr_setpshared");
+        let mut lock_attr = lock_attr.assume_init();
+        trace!("pthread_mutexattr_setpshared");
         if pthread_mutexattr_setpshared(&mut lock_attr, PTHREAD_PROCESS_SHARED) != 0 {
             return Err(From::from(
                 "Failed to set pthread_mutexattr_setpshared(PTHREAD_PROCESS_SHARED)".to_string(),
             ));
         }
-        let ptr = mem.add(padding) as *mut _;
-        //trace!("pthread_mutex_init({:p})", ptr);
+        // Lets the next owner recover the mutex when its owner dies, macOS has no robust mutexes
+        #[cfg(target_os = "linux")]
+        {
+            trace!("pthread_mutexattr_setrobust");
+            if libc::pthread_mutexattr_setrobust(&mut lock_attr, libc::PTHREAD_MUTEX_ROBUST) != 0 {
+                return Err(From::from(
+                    "Failed to set pthread_mutexattr_setrobust(PTHREAD_MUTEX_ROBUST)".to_string(),
+                ));
+            }
+        }
+        let ptr = AbiTag::write(mem, AbiKind::Mutex, size_of::<pthread_mutex_t>()) as *mut _;
+        trace!("pthread_mutex_init({:p})", ptr);
         if pthread_mutex_init(ptr, &lock_attr) != 0 {
             return Err(From::from(
                 "Failed to initialize mutex pthread_mutex_init".to_string(),
@@ -133,21 +287,26 @@ impl LockInit for Mutex {
             data: UnsafeCell::new(data),
         });
 
-        Ok((mutex, (ptr as usize - mem as usize) + Self::size_of(None)))
+        Ok((
+            mutex,
+            (ptr as usize - mem as usize) + size_of::<pthread_mutex_t>(),
+        ))
     }
 
     unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
-        let padding = mem.align_offset(size_of::<*mut u8>() as _);
+        check_align(mem, Self::ALIGN, "Mutex")?;
+        let ptr = AbiTag::check(mem, AbiKind::Mutex, size_of::<pthread_mutex_t>())? as *mut _;
 
-        let ptr = mem.add(padding) as *mut _;
-
-        //trace!("existing mutex ({:p})", ptr);
+        trace!("existing mutex ({:p})", ptr);
         let mutex = Box::new(Self {
             ptr,
             data: UnsafeCell::new(data),
         });
 
-        Ok((mutex, (ptr as usize - mem as usize) + Self::size_of(None)))
+        Ok((
+            mutex,
+            (ptr as usize - mem as usize) + size_of::<pthread_mutex_t>(),
+        ))
     }
 }
 
@@ -155,19 +314,50 @@ impl Drop for Mutex {
     fn drop(&mut self) {}
 }
 
+impl Mutex {
+    /// Converts the result of a lock call into a guard. A mutex whose owner died is made
+    /// consistent and released, then reported like on Windows.
+    fn acquired(&self, res: i32) -> Result<LockGuard<'_>> {
+        if res == 0 {
+            return Ok(LockGuard::new(self));
+        }
+        if consistent_if_owner_dead(self.ptr, res) == 0 {
+            // We own the mutex at this point
+            self.release()?;
+            return Err(Box::new(AbandonedError));
+        }
+        if res == libc::ETIMEDOUT {
+            return Err(Box::new(TimeoutError { what: "mutex" }));
+        }
+        Err(From::from(format!("Failed to acquire mutex : {}", res)))
+    }
+}
+
+/// Makes a robust mutex whose owner died consistent again when `res` is `EOWNERDEAD`, in
+/// which case the caller holds it and 0 is returned. Any other `res` is returned as is.
+pub(crate) fn consistent_if_owner_dead(mutex: *mut pthread_mutex_t, res: i32) -> i32 {
+    #[cfg(target_os = "linux")]
+    if res == libc::EOWNERDEAD {
+        warn!("pthread mutex {:p} was abandoned by its owner", mutex);
+        return unsafe { libc::pthread_mutex_consistent(mutex) };
+    }
+    let _ = mutex;
+    res
+}
+
 impl LockImpl for Mutex {
     fn as_raw(&self) -> *mut std::ffi::c_void {
         self.ptr as _
     }
 
+    fn as_mutex(&self) -> Option<*mut std::ffi::c_void> {
+        Some(self.ptr as _)
+    }
+
     fn lock(&self) -> Result<LockGuard<'_>> {
         let res = unsafe { pthread_mutex_lock(self.ptr) };
-        //trace!("pthread_mutex_lock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!("Failed to acquire mutex : {}", res)));
-        }
-
-        Ok(LockGuard::new(self))
+        trace!("pthread_mutex_lock({:p}) = {}", self.ptr, res);
+        self.acquired(res)
     }
 
     fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
@@ -177,17 +367,13 @@ impl LockImpl for Mutex {
         };
 
         let res = unsafe { pthread_mutex_timedlock(self.ptr, &timespec) };
-        //trace!("pthread_mutex_timedlock({:p})", self.ptr);
-        if res != 0 {
-            return Err(From::from(format!("Failed to acquire mutex : {}", res)));
-        }
-
-        Ok(LockGuard::new(self))
+        trace!("pthread_mutex_timedlock({:p}) = {}", self.ptr, res);
+        self.acquired(res)
     }
 
     fn release(&self) -> Result<()> {
         let res = unsafe { pthread_mutex_unlock(self.ptr) };
-        //trace!("pthread_mutex_unlock({:p})", self.ptr);
+        trace!("pthread_mutex_unlock({:p}) = {}", self.ptr, res);
         if res != 0 {
             return Err(From::from(format!("Failed to release mutex : {}", res)));
         }
@@ -204,31 +390,30 @@ pub struct RwLock {
 }
 
 impl LockInit for RwLock {
+    const SIZE: usize = AbiTag::tagged_size(size_of::<pthread_rwlock_t>());
+    const ALIGN: usize = TAG_ALIGN;
+
     fn size_of(addr: Option<*mut u8>) -> usize {
-        let padding = match addr {
-            Some(mem) => mem.align_offset(size_of::<*mut u8>() as _),
-            None => 0,
-        };
-        padding + size_of::<pthread_rwlock_t>()
+        AbiTag::size_of(addr, size_of::<pthread_rwlock_t>())
     }
 
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
-        let padding = mem.align_offset(size_of::<*mut u8>() as _);
-        #[allow(clippy::uninit_assumed_init)]
-        let mut lock_attr: pthread_rwlockattr_t = MaybeUninit::uninit().assume_init();
-        if pthread_rwlockattr_init(&mut lock_attr) != 0 {
+        check_align(mem, Self::ALIGN, "RwLock")?;
+        let mut lock_attr = MaybeUninit::<pthread_rwlockattr_t>::uninit();
+        if pthread_rwlockattr_init(lock_attr.as_mut_ptr()) != 0 {
             return Err(From::from(
                 "Failed to initialize pthread_rwlockattr_t".to_string(),
             ));
         }
+        let mut lock_attr = lock_attr.assume_init();
         if pthread_rwlockattr_setpshared(&mut lock_attr, PTHREAD_PROCESS_SHARED) != 0 {
             return Err(From::from(
                 "Failed to set pthread_rwlockattr_setpshared(PTHREAD_PROCESS_SHARED)".to_string(),
             ));
         }
-        let ptr = mem.add(padding) as *mut _;
-        //trace!("pthread_rwlock_init({:p})", ptr);
+        let ptr = AbiTag::write(mem, AbiKind::RwLock, size_of::<pthread_rwlock_t>()) as *mut _;
+        trace!("pthread_rwlock_init({:p})", ptr);
         if pthread_rwlock_init(ptr, &lock_attr) != 0 {
             return Err(From::from(
                 "Failed to initialize pthread_rwlock_init".to_string(),
@@ -240,21 +425,26 @@ impl LockInit for RwLock {
             data: UnsafeCell::new(data),
         });
 
-        Ok((lock, (ptr as usize - mem as usize) + Self::size_of(None)))
+        Ok((
+            lock,
+            (ptr as usize - mem as usize) + size_of::<pthread_rwlock_t>(),
+        ))
     }
 
     unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
-        let padding = mem.align_offset(size_of::<*mut u8>() as _);
-
-        let ptr = mem.add(padding) as *mut _;
+        check_align(mem, Self::ALIGN, "RwLock")?;
+        let ptr = AbiTag::check(mem, AbiKind::RwLock, size_of::<pthread_rwlock_t>())? as *mut _;
 
-        //trace!("existing rwlock ({:p})", ptr);
+        trace!("existing rwlock ({:p})", ptr);
         let lock = Box::new(Self {
             ptr,
             data: UnsafeCell::new(data),
         });
 
-        Ok((lock, (ptr as usize - mem as usize) + Self::size_of(None)))
+        Ok((
+            lock,
+            (ptr as usize - mem as usize) + size_of::<pthread_rwlock_t>(),
+        ))
     }
 }
 
@@ -269,7 +459,7 @@ impl LockImpl for RwLock {
 
     fn lock(&self) -> Result<LockGuard<'_>> {
         let res = unsafe { pthread_rwlock_wrlock(self.ptr) };
-        //trace!("pthread_rwlock_wrlock({:p})", self.ptr);
+        trace!("pthread_rwlock_wrlock({:p}) = {}", self.ptr, res);
         if res != 0 {
             return Err(From::from(format!(
                 "Failed to acquire writeable rwlock : {}",
@@ -287,7 +477,10 @@ impl LockImpl for RwLock {
         };
 
         let res = unsafe { pthread_rwlock_timedwrlock(self.ptr, &timespec) };
-        //trace!("pthread_rwlock_timedwrlock({:p})", self.ptr);
+        trace!("pthread_rwlock_timedwrlock({:p}) = {}", self.ptr, res);
+        if res == libc::ETIMEDOUT {
+            return Err(Box::new(TimeoutError { what: "rwlock" }));
+        }
         if res != 0 {
             return Err(From::from(format!(
                 "Failed to acquire writeable rwlock : {}",
@@ -300,7 +493,7 @@ impl LockImpl for RwLock {
 
     fn rlock(&self) -> Result<ReadLockGuard<'_>> {
         let res = unsafe { pthread_rwlock_rdlock(self.ptr) };
-        //trace!("pthread_rwlock_rdlock({:p})", self.ptr);
+        trace!("pthread_rwlock_rdlock({:p}) = {}", self.ptr, res);
         if res != 0 {
             return Err(From::from(format!(
                 "Failed to acquire readable rwlock : {}",
@@ -318,7 +511,10 @@ impl LockImpl for RwLock {
         };
 
         let res = unsafe { pthread_rwlock_timedrdlock(self.ptr, &timespec) };
-        //trace!("pthread_rwlock_timedrdlock({:p})", self.ptr);
+        trace!("pthread_rwlock_timedrdlock({:p}) = {}", self.ptr, res);
+        if res == libc::ETIMEDOUT {
+            return Err(Box::new(TimeoutError { what: "rwlock" }));
+        }
         if res != 0 {
             return Err(From::from(format!(
                 "Failed to acquire readable rwlock : {}",
@@ -331,7 +527,7 @@ impl LockImpl for RwLock {
 
     fn release(&self) -> Result<()> {
         let res = unsafe { pthread_rwlock_unlock(self.ptr) };
-        //trace!("pthread_rwlock_unlock({:p})", self.ptr);
+        trace!("pthread_rwlock_unlock({:p}) = {}", self.ptr, res);
         if res != 0 {
             return Err(From::from(format!("Failed to release rwlock : {}", res)));
         }
@@ -341,3 +537,190 @@ impl LockImpl for RwLock {
         &mut *self.data.get()
     }
 }
+
+/// Name of the semaphore whose id is stored in the shared memory
+fn sem_name(id: u32) -> CString {
+    CString::new(format!("/sem_mutex_{}", id)).unwrap()
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "macos")] {
+        /// macOS has no sem_timedwait(), poll the semaphore until the deadline instead
+        unsafe fn sem_timedwait(sem: *mut sem_t, abstime: &timespec) -> i32 {
+            let mut timenow = timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            let timesleep = timespec {
+                tv_sec: 0,
+                tv_nsec: 10_000_000, // 10ms
+            };
+            while libc::sem_trywait(sem) != 0 {
+                if std::io::Error::last_os_error().raw_os_error() != Some(libc::EAGAIN) {
+                    return -1;
+                }
+                clock_gettime(CLOCK_REALTIME, &mut timenow);
+                if (timenow.tv_sec, timenow.tv_nsec) >= (abstime.tv_sec, abstime.tv_nsec) {
+                    // Reported like the real sem_timedwait() so try_lock() returns a TimeoutError
+                    *libc::__error() = libc::ETIMEDOUT;
+                    return -1;
+                }
+                libc::nanosleep(&timesleep, std::ptr::null_mut());
+            }
+            0
+        }
+    } else {
+        use libc::sem_timedwait;
+    }
+}
+
+/// Mutex built on a POSIX named semaphore (`sem_open`). Only the semaphore's id is stored
+/// in the shared memory, the same way the Windows `Mutex` stores the id of its named mutex.
+/// The semaphore name is removed when the instance that created it is dropped.
+pub struct NamedSemMutex {
+    id: u32,
+    sem: *mut sem_t,
+    owner: bool,
+    data: UnsafeCell<*mut u8>,
+}
+
+impl LockInit for NamedSemMutex {
+    const SIZE: usize = size_of::<u32>();
+    const ALIGN: usize = align_of::<u32>();
+
+    fn size_of(_addr: Option<*mut u8>) -> usize {
+        size_of::<u32>()
+    }
+
+    #[allow(clippy::new_ret_no_self)]
+    unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "NamedSemMutex")?;
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut id = seed ^ std::process::id().rotate_left(16);
+        let sem = loop {
+            let name = sem_name(id);
+            let sem = sem_open(
+                name.as_ptr(),
+                O_CREAT | O_EXCL,
+                0o600 as libc::c_uint,
+                1 as libc::c_uint,
+            );
+            trace!("sem_open({:?}, O_CREAT | O_EXCL) = {:p}", name, sem);
+            if sem != SEM_FAILED {
+                break sem;
+            }
+            if std::io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST) {
+                return Err(From::from(format!(
+                    "Failed to create semaphore {:?} : {}",
+                    name,
+                    std::io::Error::last_os_error()
+                )));
+            }
+            id = id.wrapping_add(1);
+        };
+        *(mem as *mut u32) = id;
+
+        let obj = Box::new(Self {
+            id,
+            sem,
+            owner: true,
+            data: UnsafeCell::new(data),
+        });
+        Ok((obj, Self::size_of(None)))
+    }
+
+    unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "NamedSemMutex")?;
+        let id = *(mem as *mut u32);
+        let name = sem_name(id);
+        let sem = sem_open(name.as_ptr(), 0);
+        trace!("sem_open({:?}) = {:p}", name, sem);
+        if sem == SEM_FAILED {
+            return Err(From::from(format!(
+                "Failed to open semaphore {:?} : {}",
+                name,
+                std::io::Error::last_os_error()
+            )));
+        }
+
+        let obj = Box::new(Self {
+            id,
+            sem,
+            owner: false,
+            data: UnsafeCell::new(data),
+        });
+        Ok((obj, Self::size_of(None)))
+    }
+}
+
+impl Drop for NamedSemMutex {
+    fn drop(&mut self) {
+        trace!("sem_close({:p})", self.sem);
+        unsafe { sem_close(self.sem) };
+        if self.owner {
+            let name = sem_name(self.id);
+            trace!("sem_unlink({:?})", name);
+            unsafe { sem_unlink(name.as_ptr()) };
+        }
+    }
+}
+
+impl LockImpl for NamedSemMutex {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        self.sem as _
+    }
+
+    fn lock(&self) -> Result<LockGuard<'_>> {
+        loop {
+            let res = unsafe { sem_wait(self.sem) };
+            trace!("sem_wait({:p}) = {}", self.sem, res);
+            if res == 0 {
+                return Ok(LockGuard::new(self));
+            }
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINTR) {
+                return Err(From::from(format!("Failed to acquire semaphore : {}", err)));
+            }
+        }
+    }
+
+    fn try_lock(&self, timeout: Timeout) -> Result<LockGuard<'_>> {
+        let timespec: timespec = match timeout {
+            Timeout::Infinite => return self.lock(),
+            Timeout::Val(d) => abs_timespec_from_duration(d),
+        };
+
+        loop {
+            let res = unsafe { sem_timedwait(self.sem, &timespec) };
+            trace!("sem_timedwait({:p}) = {}", self.sem, res);
+            if res == 0 {
+                return Ok(LockGuard::new(self));
+            }
+            let err = std::io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR) => {}
+                Some(libc::ETIMEDOUT) => return Err(Box::new(TimeoutError { what: "semaphore" })),
+                _ => return Err(From::from(format!("Failed to acquire semaphore : {}", err))),
+            }
+        }
+    }
+
+    fn release(&self) -> Result<()> {
+        let res = unsafe { sem_post(self.sem) };
+        trace!("sem_post({:p}) = {}", self.sem, res);
+        if res != 0 {
+            return Err(From::from(format!(
+                "Failed to release semaphore : {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    }
+
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        &mut *self.data.get()
+    }
+}
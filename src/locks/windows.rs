@@ -1,11 +1,14 @@
 use std::cell::UnsafeCell;
 use std::ffi::CString;
-use std::mem::size_of;
+use std::mem::{align_of, size_of};
 use std::ptr::null_mut;
 
 pub const MUTEX_ALL_ACCESS: u32 = 0x1F0001;
 use winapi::{
-    shared::ntdef::{FALSE, NULL},
+    shared::{
+        ntdef::{FALSE, NULL},
+        winerror::WAIT_TIMEOUT,
+    },
     um::{
         handleapi::CloseHandle,
         synchapi::{CreateMutexExA, ReleaseMutex, WaitForSingleObject, CREATE_MUTEX_INITIAL_OWNER},
@@ -14,8 +17,8 @@ use winapi::{
     },
 };
 
-use super::{LockGuard, LockImpl, LockInit};
-use crate::{Result, Timeout};
+use super::{AbandonedError, LockGuard, LockImpl, LockInit, TimeoutError};
+use crate::{check_align, Result, Timeout};
 
 pub struct Mutex {
     handle: HANDLE,
@@ -23,19 +26,28 @@ pub struct Mutex {
 }
 
 impl LockInit for Mutex {
+    const SIZE: usize = size_of::<u32>();
+    const ALIGN: usize = align_of::<u32>();
+
     fn size_of(_addr: Option<*mut u8>) -> usize {
         size_of::<u32>()
     }
 
     #[allow(clippy::new_ret_no_self)]
     unsafe fn new(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Mutex")?;
         // Find a mutex id that doesnt collide with another
         let mut mutex_handle: HANDLE = NULL;
         let mut mutex_id: u32 = 0;
         while mutex_handle == NULL {
             mutex_id = rand::random::<u32>();
             let path = CString::new(format!("mutex_{}", mutex_id)).unwrap();
-            //trace!("CreateMutexExA(NULL, '{}', 0x{:X}, 0x{:X})",path.to_string_lossy(),CREATE_MUTEX_INITIAL_OWNER,MUTEX_ALL_ACCESS);
+            trace!(
+                "CreateMutexExA(NULL, '{}', 0x{:X}, 0x{:X})",
+                path.to_string_lossy(),
+                CREATE_MUTEX_INITIAL_OWNER,
+                MUTEX_ALL_ACCESS
+            );
             mutex_handle = CreateMutexExA(
                 null_mut(),
                 path.as_ptr() as *mut _,
@@ -58,9 +70,15 @@ impl LockInit for Mutex {
     }
 
     unsafe fn from_existing(mem: *mut u8, data: *mut u8) -> Result<(Box<dyn LockImpl>, usize)> {
+        check_align(mem, Self::ALIGN, "Mutex")?;
         let mutex_id = *(mem as *mut u32);
         let path = CString::new(format!("mutex_{}", mutex_id)).unwrap();
-        //trace!("OpenMutexA(0x{:X}, 0x{:X}, '{}')", SYNCHRONIZE,FALSE,path.to_string_lossy());
+        trace!(
+            "OpenMutexA(0x{:X}, 0x{:X}, '{}')",
+            SYNCHRONIZE,
+            FALSE,
+            path.to_string_lossy()
+        );
         let mutex_handle = OpenMutexA(SYNCHRONIZE, FALSE as _, path.as_ptr() as *mut _);
         if mutex_handle == NULL {
             return Err(From::from(format!(
@@ -80,7 +98,7 @@ impl LockInit for Mutex {
 
 impl Drop for Mutex {
     fn drop(&mut self) {
-        //trace!("CloseHandle(0x{:X})", self.handle as usize);
+        trace!("CloseHandle(0x{:X})", self.handle as usize);
         unsafe { CloseHandle(self.handle) };
     }
 }
@@ -90,13 +108,23 @@ impl LockImpl for Mutex {
         self.handle as _
     }
 
+    fn as_mutex(&self) -> Option<*mut std::ffi::c_void> {
+        Some(self.handle as _)
+    }
+
     fn lock(&self) -> Result<LockGuard<'_>> {
         let wait_res = unsafe { WaitForSingleObject(self.handle, INFINITE) };
-        //trace!("WaitForSingleObject(0x{:X})", self.handle as usize);
+        trace!(
+            "WaitForSingleObject(0x{:X}) = 0x{:X}",
+            self.handle as usize,
+            wait_res
+        );
         if wait_res == WAIT_OBJECT_0 {
             Ok(LockGuard::new(self))
         } else if wait_res == WAIT_ABANDONED {
-            panic!("A thread holding the mutex has left it in a poisened state");
+            // We own the mutex at this point
+            self.release()?;
+            Err(Box::new(AbandonedError))
         } else {
             Err(From::from(format!(
                 "Failed to aquire lock with value : 0x{:X}",
@@ -115,11 +143,19 @@ impl LockImpl for Mutex {
                 },
             )
         };
-        //trace!("WaitForSingleObject(0x{:X})", self.handle as usize);
+        trace!(
+            "WaitForSingleObject(0x{:X}) = 0x{:X}",
+            self.handle as usize,
+            wait_res
+        );
         if wait_res == WAIT_OBJECT_0 {
             Ok(LockGuard::new(self))
         } else if wait_res == WAIT_ABANDONED {
-            panic!("A thread holding the mutex has left it in a poisened state");
+            // We own the mutex at this point
+            self.release()?;
+            Err(Box::new(AbandonedError))
+        } else if wait_res == WAIT_TIMEOUT {
+            Err(Box::new(TimeoutError { what: "mutex" }))
         } else {
             Err(From::from(format!(
                 "Failed to aquire lock with value : 0x{:X}",
@@ -129,7 +165,7 @@ impl LockImpl for Mutex {
     }
 
     fn release(&self) -> Result<()> {
-        //trace!("ReleaseMutex(0x{:X})", self.handle as usize);
+        trace!("ReleaseMutex(0x{:X})", self.handle as usize);
         if unsafe { ReleaseMutex(self.handle) } == 0 {
             Err(From::from(
                 "Could not release mutex as we did not own it".to_string(),
@@ -0,0 +1,91 @@
+//! Primitives placed at an offset of a memory mapping owned by another crate.
+//!
+//! `create_in()` and `open_in()` check that the primitive fits in the mapping and return a
+//! handle borrowing it, so the mapping cannot be unmapped while the primitive is in use.
+
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use crate::Result;
+
+/// Shared memory mapping primitives can be placed in
+/// # Safety
+/// `as_mut_ptr()` must return the start of `len()` bytes that stay mapped and writable
+/// through shared references for as long as the mapping is borrowed.
+pub unsafe trait Mapping {
+    /// Start of the mapping
+    fn as_mut_ptr(&self) -> *mut u8;
+    /// Size of the mapping in bytes
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// `MmapMut` only hands out its pointer through `&mut`, convert it with `MmapRaw::from()`
+#[cfg(feature = "memmap2")]
+unsafe impl Mapping for memmap2::MmapRaw {
+    fn as_mut_ptr(&self) -> *mut u8 {
+        memmap2::MmapRaw::as_mut_ptr(self)
+    }
+    fn len(&self) -> usize {
+        memmap2::MmapRaw::len(self)
+    }
+}
+
+#[cfg(feature = "shared_memory")]
+unsafe impl Mapping for shared_memory::Shmem {
+    fn as_mut_ptr(&self) -> *mut u8 {
+        self.as_ptr()
+    }
+    fn len(&self) -> usize {
+        shared_memory::Shmem::len(self)
+    }
+}
+
+/// Primitive placed in a `Mapping` it borrows for its whole life
+pub struct Mapped<'a, T> {
+    obj: T,
+    _map: PhantomData<&'a ()>,
+}
+
+impl<T> Deref for Mapped<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.obj
+    }
+}
+
+/// Returns the address `offset` bytes into `map` once checked that the `size` bytes
+/// `used(address)` returns fit in it
+pub(crate) fn at<M: Mapping, F: FnOnce(*mut u8) -> usize>(
+    map: &M,
+    offset: usize,
+    used: F,
+) -> Result<*mut u8> {
+    if offset > map.len() {
+        return Err(From::from(format!(
+            "Offset {} is past the end of the {} byte mapping",
+            offset,
+            map.len()
+        )));
+    }
+    let mem = unsafe { map.as_mut_ptr().add(offset) };
+    match offset.checked_add(used(mem)) {
+        Some(end) if end <= map.len() => Ok(mem),
+        _ => Err(From::from(format!(
+            "Primitive at offset {} does not fit in the {} byte mapping",
+            offset,
+            map.len()
+        ))),
+    }
+}
+
+/// Ties `obj` to the lifetime of `map`
+pub(crate) fn wrap<M: Mapping, T>(_map: &M, obj: T) -> Mapped<'_, T> {
+    Mapped {
+        obj,
+        _map: PhantomData,
+    }
+}
@@ -0,0 +1,94 @@
+//! Primitives living in a shared memory object created from a name, for processes that
+//! only agree on a name instead of sharing memory themselves.
+//!
+//! The object is a `shm_open()` segment on Unix and a file mapping on Windows. It starts
+//! with a small header telling openers whether the creator finished initializing it.
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "windows")] {
+        mod windows;
+        use windows as os;
+    } else if #[cfg(target_family = "unix")] {
+        mod unix;
+        use unix as os;
+    } else {
+        unimplemented!("This crate does not support your OS yet !");
+    }
+}
+use std::ops::Deref;
+
+use crate::sync::{AtomicU32, Ordering};
+use crate::Result;
+
+/// Room reserved before the primitive, large enough to keep it aligned for every platform
+const HEADER_SIZE: usize = 64;
+
+#[repr(C)]
+struct Header {
+    ready: AtomicU32,
+}
+
+/// Primitive stored in a named shared memory object. The object is unmapped when this is
+/// dropped and its name is removed when the creator drops it, processes that already
+/// opened it keep using it.
+pub struct Named<T> {
+    // Declared first so the primitive is dropped before its memory is unmapped
+    obj: T,
+    map: os::Mapping,
+}
+
+impl<T> Named<T> {
+    /// Name of the shared memory object
+    pub fn name(&self) -> &str {
+        self.map.name()
+    }
+
+    /// True if this handle created the shared memory object
+    pub fn is_owner(&self) -> bool {
+        self.map.is_owner()
+    }
+}
+
+impl<T> Deref for Named<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.obj
+    }
+}
+
+/// Returns the 8 byte aligned address following the `used` bytes of a primitive at `mem`
+pub(crate) unsafe fn data_after(mem: *mut u8, used: usize) -> *mut u8 {
+    let ptr = mem.add(used);
+    ptr.add(ptr.align_offset(std::mem::size_of::<u64>()))
+}
+
+/// Creates the object `name` with room for `size` bytes and initializes it with `init`
+pub(crate) fn create<T, F: FnOnce(*mut u8) -> Result<T>>(
+    name: &str,
+    size: usize,
+    init: F,
+) -> Result<Named<T>> {
+    // `size_of(None)` does not count alignment padding, keep some slack for it
+    let map = os::Mapping::create(name, HEADER_SIZE + size + HEADER_SIZE)?;
+    let header = map.as_ptr() as *const Header;
+    let obj = init(unsafe { map.as_ptr().add(HEADER_SIZE) })?;
+    unsafe { &(*header).ready }.store(1, Ordering::Release);
+    Ok(Named { obj, map })
+}
+
+/// Opens the existing object `name` and re-uses the primitive in it with `init`
+pub(crate) fn open<T, F: FnOnce(*mut u8) -> Result<T>>(name: &str, init: F) -> Result<Named<T>> {
+    let map = os::Mapping::open(name)?;
+    if map.len() < HEADER_SIZE {
+        return Err(From::from(format!("Named object '{}' is too small", name)));
+    }
+    let header = map.as_ptr() as *const Header;
+    if unsafe { &(*header).ready }.load(Ordering::Acquire) != 1 {
+        return Err(From::from(format!(
+            "Named object '{}' is not initialized yet",
+            name
+        )));
+    }
+    let obj = init(unsafe { map.as_ptr().add(HEADER_SIZE) })?;
+    Ok(Named { obj, map })
+}
@@ -0,0 +1,137 @@
+use std::ffi::CString;
+use std::ptr::null_mut;
+
+use libc::{
+    c_void, close, fstat, ftruncate, mmap, munmap, shm_open, shm_unlink, MAP_FAILED, MAP_SHARED,
+    O_CREAT, O_EXCL, O_RDWR, PROT_READ, PROT_WRITE,
+};
+
+use crate::Result;
+
+pub(crate) struct Mapping {
+    name: String,
+    ptr: *mut u8,
+    len: usize,
+    owner: bool,
+}
+
+/// `shm_open()` names must start with a single slash
+fn os_name(name: &str) -> Result<CString> {
+    let name = format!("/{}", name.trim_start_matches('/'));
+    CString::new(name).map_err(|_| From::from("Named object names cannot contain NUL bytes"))
+}
+
+unsafe fn map_fd(fd: i32, len: usize) -> Result<*mut u8> {
+    trace!("mmap({}, {})", fd, len);
+    let ptr = mmap(null_mut(), len, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    if ptr == MAP_FAILED {
+        return Err(From::from(format!(
+            "Failed to map named object : {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(ptr as *mut u8)
+}
+
+impl Mapping {
+    pub(crate) fn create(name: &str, len: usize) -> Result<Self> {
+        let os_name = os_name(name)?;
+        unsafe {
+            let fd = shm_open(os_name.as_ptr(), O_CREAT | O_EXCL | O_RDWR, 0o600);
+            trace!("shm_open({:?}, O_CREAT | O_EXCL) = {}", os_name, fd);
+            if fd < 0 {
+                return Err(From::from(format!(
+                    "Failed to create named object '{}' : {}",
+                    name,
+                    std::io::Error::last_os_error()
+                )));
+            }
+            let res = if ftruncate(fd, len as _) != 0 {
+                Err(From::from(format!(
+                    "Failed to size named object '{}' : {}",
+                    name,
+                    std::io::Error::last_os_error()
+                )))
+            } else {
+                map_fd(fd, len)
+            };
+            close(fd);
+
+            match res {
+                Ok(ptr) => Ok(Self {
+                    name: name.to_string(),
+                    ptr,
+                    len,
+                    owner: true,
+                }),
+                Err(e) => {
+                    shm_unlink(os_name.as_ptr());
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    pub(crate) fn open(name: &str) -> Result<Self> {
+        let os_name = os_name(name)?;
+        unsafe {
+            let fd = shm_open(os_name.as_ptr(), O_RDWR, 0o600);
+            trace!("shm_open({:?}) = {}", os_name, fd);
+            if fd < 0 {
+                return Err(From::from(format!(
+                    "Failed to open named object '{}' : {}",
+                    name,
+                    std::io::Error::last_os_error()
+                )));
+            }
+            let mut stat: libc::stat = std::mem::zeroed();
+            let res = if fstat(fd, &mut stat) != 0 {
+                Err(From::from(format!(
+                    "Failed to get the size of named object '{}' : {}",
+                    name,
+                    std::io::Error::last_os_error()
+                )))
+            } else {
+                map_fd(fd, stat.st_size as usize).map(|ptr| (ptr, stat.st_size as usize))
+            };
+            close(fd);
+
+            let (ptr, len) = res?;
+            Ok(Self {
+                name: name.to_string(),
+                ptr,
+                len,
+                owner: false,
+            })
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn is_owner(&self) -> bool {
+        self.owner
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        trace!("munmap({:p}, {})", self.ptr, self.len);
+        unsafe { munmap(self.ptr as *mut c_void, self.len) };
+        if self.owner {
+            if let Ok(os_name) = os_name(&self.name) {
+                trace!("shm_unlink({:?})", os_name);
+                unsafe { shm_unlink(os_name.as_ptr()) };
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::ffi::CString;
+use std::mem::{size_of, zeroed};
+use std::ptr::null_mut;
+
+use winapi::{
+    shared::{
+        ntdef::{FALSE, NULL},
+        winerror::ERROR_ALREADY_EXISTS,
+    },
+    um::{
+        errhandlingapi::GetLastError,
+        handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+        memoryapi::{MapViewOfFile, UnmapViewOfFile, VirtualQuery, FILE_MAP_ALL_ACCESS},
+        winbase::{CreateFileMappingA, OpenFileMappingA},
+        winnt::{HANDLE, MEMORY_BASIC_INFORMATION, PAGE_READWRITE},
+    },
+};
+
+use crate::Result;
+
+/// File mapping backed by the paging file, Windows deletes it with its last handle
+pub(crate) struct Mapping {
+    name: String,
+    handle: HANDLE,
+    ptr: *mut u8,
+    len: usize,
+    owner: bool,
+}
+
+fn os_name(name: &str) -> Result<CString> {
+    CString::new(name).map_err(|_| From::from("Named object names cannot contain NUL bytes"))
+}
+
+impl Mapping {
+    pub(crate) fn create(name: &str, len: usize) -> Result<Self> {
+        let os_name = os_name(name)?;
+        unsafe {
+            let handle = CreateFileMappingA(
+                INVALID_HANDLE_VALUE,
+                null_mut(),
+                PAGE_READWRITE,
+                ((len as u64) >> 32) as u32,
+                len as u32,
+                os_name.as_ptr(),
+            );
+            trace!(
+                "CreateFileMappingA('{}', {}) = 0x{:X}",
+                name,
+                len,
+                handle as usize
+            );
+            if handle == NULL {
+                return Err(From::from(format!(
+                    "Failed to create named object '{}' : 0x{:X}",
+                    name,
+                    GetLastError()
+                )));
+            }
+            if GetLastError() == ERROR_ALREADY_EXISTS {
+                CloseHandle(handle);
+                return Err(From::from(format!(
+                    "Named object '{}' already exists",
+                    name
+                )));
+            }
+            let ptr = MapViewOfFile(handle, FILE_MAP_ALL_ACCESS, 0, 0, len);
+            if ptr.is_null() {
+                CloseHandle(handle);
+                return Err(From::from(format!(
+                    "Failed to map named object '{}' : 0x{:X}",
+                    name,
+                    GetLastError()
+                )));
+            }
+            Ok(Self {
+                name: name.to_string(),
+                handle,
+                ptr: ptr as *mut u8,
+                len,
+                owner: true,
+            })
+        }
+    }
+
+    pub(crate) fn open(name: &str) -> Result<Self> {
+        let os_name = os_name(name)?;
+        unsafe {
+            let handle = OpenFileMappingA(FILE_MAP_ALL_ACCESS, FALSE as _, os_name.as_ptr());
+            trace!("OpenFileMappingA('{}') = 0x{:X}", name, handle as usize);
+            if handle == NULL {
+                return Err(From::from(format!(
+                    "Failed to open named object '{}' : 0x{:X}",
+                    name,
+                    GetLastError()
+                )));
+            }
+            let ptr = MapViewOfFile(handle, FILE_MAP_ALL_ACCESS, 0, 0, 0);
+            if ptr.is_null() {
+                CloseHandle(handle);
+                return Err(From::from(format!(
+                    "Failed to map named object '{}' : 0x{:X}",
+                    name,
+                    GetLastError()
+                )));
+            }
+            // The view covers the whole mapping, rounded up to the page size
+            let mut info: MEMORY_BASIC_INFORMATION = zeroed();
+            VirtualQuery(ptr, &mut info, size_of::<MEMORY_BASIC_INFORMATION>());
+            Ok(Self {
+                name: name.to_string(),
+                handle,
+                ptr: ptr as *mut u8,
+                len: info.RegionSize,
+                owner: false,
+            })
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn is_owner(&self) -> bool {
+        self.owner
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        trace!("UnmapViewOfFile({:p})", self.ptr);
+        unsafe {
+            UnmapViewOfFile(self.ptr as _);
+            CloseHandle(self.handle);
+        }
+    }
+}
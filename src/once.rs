@@ -0,0 +1,173 @@
+//! One time initialization shared by every process mapping the same memory.
+//!
+//! The state and the pid of the initializing process are packed in a single word so a
+//! process that finds the initializer dead can atomically take over. Waiters poll the
+//! state instead of blocking on a lock that the dead initializer could have kept.
+
+use std::mem::{align_of, size_of};
+use std::time::Duration;
+
+use crate::sync::{AtomicU64, Ordering};
+use crate::{check_align, Deadline, Result, Timeout};
+
+const INCOMPLETE: u64 = 0;
+const RUNNING: u64 = 1;
+const COMPLETE: u64 = 2;
+
+/// Longest sleep between two checks of the state
+const MAX_POLL: Duration = Duration::from_millis(10);
+
+fn running_word(pid: u32) -> u64 {
+    ((pid as u64) << 32) | RUNNING
+}
+
+/// Returns false once `pid` has exited. A process that exited but was not reaped by its
+/// parent yet is still considered alive.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    use winapi::{
+        shared::{
+            ntdef::{FALSE, NULL},
+            winerror::ERROR_INVALID_PARAMETER,
+        },
+        um::{
+            errhandlingapi::GetLastError, handleapi::CloseHandle, processthreadsapi::OpenProcess,
+            synchapi::WaitForSingleObject, winbase::WAIT_OBJECT_0, winnt::SYNCHRONIZE,
+        },
+    };
+    unsafe {
+        let handle = OpenProcess(SYNCHRONIZE, FALSE as _, pid);
+        if handle == NULL {
+            // Access denied still means the process exists
+            return GetLastError() != ERROR_INVALID_PARAMETER;
+        }
+        let exited = WaitForSingleObject(handle, 0) == WAIT_OBJECT_0;
+        CloseHandle(handle);
+        !exited
+    }
+}
+
+/// Resets the state if the initialization closure panics so another caller can retry
+struct ResetOnUnwind<'a>(&'a AtomicU64);
+impl Drop for ResetOnUnwind<'_> {
+    fn drop(&mut self) {
+        warn!("SharedOnce({:p}) initializer panicked", self.0);
+        self.0.store(INCOMPLETE, Ordering::Release);
+    }
+}
+
+/// Runs an initialization closure exactly once across every process, like `std::sync::Once`.
+/// If the process running the closure dies before it completes, the next caller of
+/// `call_once()` runs its own closure instead.
+pub struct SharedOnce {
+    state: *const AtomicU64,
+}
+
+impl SharedOnce {
+    /// Size of the once's internal representation at an address aligned to `ALIGN`
+    pub const SIZE: usize = size_of::<AtomicU64>();
+    /// Alignment required for the memory given to `new()` and `from_existing()`
+    pub const ALIGN: usize = align_of::<AtomicU64>();
+
+    /// Size required for the once's internal representation
+    pub fn size_of(addr: Option<*mut u8>) -> usize {
+        let padding = match addr {
+            Some(mem) => mem.align_offset(Self::ALIGN),
+            None => 0,
+        };
+        padding + Self::SIZE
+    }
+
+    /// Initializes a new once in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn new(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "SharedOnce")?;
+        let state = mem as *mut AtomicU64;
+        state.write(AtomicU64::new(INCOMPLETE));
+        Ok((Self { state }, Self::SIZE))
+    }
+
+    /// Re-uses a once from an already initialized location and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn from_existing(mem: *mut u8) -> Result<(Self, usize)> {
+        check_align(mem, Self::ALIGN, "SharedOnce")?;
+        let state = mem as *mut AtomicU64;
+        if (*state).load(Ordering::Relaxed) & 0xFFFF_FFFF > COMPLETE {
+            return Err(From::from("Existing SharedOnce is corrupted"));
+        }
+        Ok((Self { state }, Self::SIZE))
+    }
+
+    fn state(&self) -> &AtomicU64 {
+        unsafe { &*self.state }
+    }
+
+    /// Returns true once an initialization closure completed
+    pub fn is_completed(&self) -> bool {
+        self.state().load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Runs `f` if no process completed an initialization yet. Returns once the
+    /// initialization is complete, waiting for another process running its closure if needed.
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        let me = running_word(std::process::id());
+        let mut poll = Duration::from_micros(50);
+        loop {
+            let cur = self.state().load(Ordering::Acquire);
+            let claimed = match cur {
+                COMPLETE => return,
+                INCOMPLETE => self
+                    .state()
+                    .compare_exchange(INCOMPLETE, me, Ordering::Acquire, Ordering::Acquire)
+                    .is_ok(),
+                _ if !process_alive((cur >> 32) as u32) => {
+                    warn!(
+                        "SharedOnce({:p}) initializer {} died, taking over",
+                        self.state,
+                        cur >> 32
+                    );
+                    self.state()
+                        .compare_exchange(cur, me, Ordering::Acquire, Ordering::Acquire)
+                        .is_ok()
+                }
+                _ => false,
+            };
+
+            if claimed {
+                trace!("SharedOnce({:p}) running initializer", self.state);
+                let reset = ResetOnUnwind(self.state());
+                f();
+                std::mem::forget(reset);
+                self.state().store(COMPLETE, Ordering::Release);
+                return;
+            }
+
+            std::thread::sleep(poll);
+            poll = std::cmp::min(poll * 2, MAX_POLL);
+        }
+    }
+
+    /// Waits for an initialization to complete without ever running one
+    pub fn wait_initialized(&self, timeout: Timeout) -> Result<()> {
+        let deadline = Deadline::new(timeout);
+        let mut poll = Duration::from_micros(50);
+        while !self.is_completed() {
+            let sleep = match deadline.remaining() {
+                Some(Timeout::Infinite) => poll,
+                Some(Timeout::Val(d)) if !d.is_zero() => std::cmp::min(poll, d),
+                _ => return Err(From::from("Timed out waiting for initialization")),
+            };
+            std::thread::sleep(sleep);
+            poll = std::cmp::min(poll * 2, MAX_POLL);
+        }
+        Ok(())
+    }
+}
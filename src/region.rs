@@ -0,0 +1,447 @@
+//! Packs several primitives and user data blocks into one shared memory segment.
+//!
+//! A `Layout` is built once from the list of kinds, which fixes the offset of every entry.
+//! `create()` initializes all of them and `open()` reattaches them in another process. The
+//! region starts with a fingerprint of its kinds and offsets, so a process opening it with a
+//! different layout fails instead of misreading the memory.
+//!
+//! A lock entry directly followed by a `Kind::Data` entry protects it : its guards dereference
+//! to the start of that data block. `Poison` wrappers are not region kinds, reserve a
+//! `Kind::Data` entry of `Poison::<L>::SIZE` bytes aligned to `Poison::<L>::ALIGN` and
+//! initialize it with `Poison::<L>::new()` instead.
+
+use std::mem::{align_of, size_of};
+use std::ptr::null_mut;
+
+use crate::events::{
+    Broadcast, BusyEvent, Condvar, CountingEvent, Event, EventGroup, EventImpl, EventInit, Latch,
+};
+#[cfg(target_os = "linux")]
+use crate::locks::{FileLock, SysVSemaphore};
+use crate::locks::{LockImpl, LockInit, Mutex, SeqLock};
+#[cfg(unix)]
+use crate::locks::{NamedSemMutex, RwLock};
+use crate::mapped::{self, Mapped, Mapping};
+use crate::once::SharedOnce;
+use crate::sync::{AtomicU64, Ordering};
+use crate::{check_align, max_align, Result};
+
+/// Fingerprint written at the start of the region, 0 until every entry is initialized
+#[repr(C)]
+struct Header {
+    fingerprint: AtomicU64,
+    entries: u32,
+}
+
+/// Offset of the first entry
+const ENTRIES: usize = size_of::<Header>();
+
+/// Kind of an entry in a region
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Mutex,
+    #[cfg(unix)]
+    RwLock,
+    Event {
+        auto_reset: bool,
+    },
+    BusyEvent {
+        auto_reset: bool,
+    },
+    Broadcast,
+    Condvar,
+    EventGroup,
+    CountingEvent,
+    Latch {
+        count: u32,
+    },
+    SeqLock,
+    SharedOnce,
+    #[cfg(unix)]
+    NamedSemMutex,
+    /// Lock on the whole of a lock file created in the temporary directory
+    #[cfg(target_os = "linux")]
+    FileLock,
+    /// Semaphore holding a single permit, used as a mutex
+    #[cfg(target_os = "linux")]
+    SysVSemaphore,
+    /// User data block of `size` bytes aligned to `align`, left zeroed by `create()`
+    Data {
+        size: usize,
+        align: usize,
+    },
+}
+
+impl Kind {
+    /// Identifies the kind in the fingerprint, initial values are left out
+    fn code(&self) -> u8 {
+        match self {
+            Kind::Mutex => 1,
+            #[cfg(unix)]
+            Kind::RwLock => 2,
+            Kind::Event { .. } => 3,
+            Kind::BusyEvent { .. } => 4,
+            Kind::Broadcast => 5,
+            Kind::Condvar => 6,
+            Kind::EventGroup => 7,
+            Kind::CountingEvent => 8,
+            Kind::Latch { .. } => 9,
+            Kind::SeqLock => 10,
+            Kind::SharedOnce => 11,
+            Kind::Data { .. } => 12,
+            #[cfg(unix)]
+            Kind::NamedSemMutex => 13,
+            #[cfg(target_os = "linux")]
+            Kind::FileLock => 14,
+            #[cfg(target_os = "linux")]
+            Kind::SysVSemaphore => 15,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            Kind::Mutex => Mutex::SIZE,
+            #[cfg(unix)]
+            Kind::RwLock => RwLock::SIZE,
+            Kind::Event { .. } => Event::SIZE,
+            Kind::BusyEvent { .. } => BusyEvent::SIZE,
+            Kind::Broadcast => Broadcast::SIZE,
+            Kind::Condvar => Condvar::SIZE,
+            Kind::EventGroup => EventGroup::SIZE,
+            Kind::CountingEvent => CountingEvent::SIZE,
+            Kind::Latch { .. } => Latch::SIZE,
+            Kind::SeqLock => SeqLock::SIZE,
+            Kind::SharedOnce => SharedOnce::SIZE,
+            Kind::Data { size, .. } => size,
+            #[cfg(unix)]
+            Kind::NamedSemMutex => NamedSemMutex::SIZE,
+            #[cfg(target_os = "linux")]
+            Kind::FileLock => FileLock::SIZE,
+            #[cfg(target_os = "linux")]
+            Kind::SysVSemaphore => SysVSemaphore::SIZE,
+        }
+    }
+
+    fn align(&self) -> usize {
+        match *self {
+            Kind::Mutex => Mutex::ALIGN,
+            #[cfg(unix)]
+            Kind::RwLock => RwLock::ALIGN,
+            Kind::Event { .. } => Event::ALIGN,
+            Kind::BusyEvent { .. } => BusyEvent::ALIGN,
+            Kind::Broadcast => Broadcast::ALIGN,
+            Kind::Condvar => Condvar::ALIGN,
+            Kind::EventGroup => EventGroup::ALIGN,
+            Kind::CountingEvent => CountingEvent::ALIGN,
+            Kind::Latch { .. } => Latch::ALIGN,
+            Kind::SeqLock => SeqLock::ALIGN,
+            Kind::SharedOnce => SharedOnce::ALIGN,
+            Kind::Data { align, .. } => align,
+            #[cfg(unix)]
+            Kind::NamedSemMutex => NamedSemMutex::ALIGN,
+            #[cfg(target_os = "linux")]
+            Kind::FileLock => FileLock::ALIGN,
+            #[cfg(target_os = "linux")]
+            Kind::SysVSemaphore => SysVSemaphore::ALIGN,
+        }
+    }
+
+    /// `data` is the block protected by a lock entry, null for other kinds
+    unsafe fn init(&self, mem: *mut u8, data: *mut u8) -> Result<Entry> {
+        Ok(match *self {
+            Kind::Mutex => Entry::Lock(Mutex::new(mem, data)?.0),
+            #[cfg(unix)]
+            Kind::RwLock => Entry::Lock(RwLock::new(mem, data)?.0),
+            Kind::Event { auto_reset } => Entry::Event(Event::new(mem, auto_reset)?.0),
+            Kind::BusyEvent { auto_reset } => Entry::Event(BusyEvent::new(mem, auto_reset)?.0),
+            Kind::Broadcast => Entry::Broadcast(Broadcast::new(mem)?.0),
+            Kind::Condvar => Entry::Condvar(Condvar::new(mem)?.0),
+            Kind::EventGroup => Entry::EventGroup(EventGroup::new(mem)?.0),
+            Kind::CountingEvent => Entry::CountingEvent(CountingEvent::new(mem)?.0),
+            Kind::Latch { count } => Entry::Latch(Latch::new(mem, count)?.0),
+            Kind::SeqLock => Entry::SeqLock(SeqLock::new(mem, data)?.0),
+            Kind::SharedOnce => Entry::SharedOnce(SharedOnce::new(mem)?.0),
+            Kind::Data { size, .. } => {
+                mem.write_bytes(0, size);
+                Entry::Data(mem)
+            }
+            #[cfg(unix)]
+            Kind::NamedSemMutex => Entry::Lock(NamedSemMutex::new(mem, data)?.0),
+            #[cfg(target_os = "linux")]
+            Kind::FileLock => Entry::Lock(FileLock::new(mem, data)?.0),
+            #[cfg(target_os = "linux")]
+            Kind::SysVSemaphore => Entry::Lock(SysVSemaphore::new(mem, data)?.0),
+        })
+    }
+
+    unsafe fn attach(&self, mem: *mut u8, data: *mut u8) -> Result<Entry> {
+        Ok(match *self {
+            Kind::Mutex => Entry::Lock(Mutex::from_existing(mem, data)?.0),
+            #[cfg(unix)]
+            Kind::RwLock => Entry::Lock(RwLock::from_existing(mem, data)?.0),
+            Kind::Event { .. } => Entry::Event(Event::from_existing(mem)?.0),
+            Kind::BusyEvent { .. } => Entry::Event(BusyEvent::from_existing(mem)?.0),
+            Kind::Broadcast => Entry::Broadcast(Broadcast::from_existing(mem)?.0),
+            Kind::Condvar => Entry::Condvar(Condvar::from_existing(mem)?.0),
+            Kind::EventGroup => Entry::EventGroup(EventGroup::from_existing(mem)?.0),
+            Kind::CountingEvent => Entry::CountingEvent(CountingEvent::from_existing(mem)?.0),
+            Kind::Latch { .. } => Entry::Latch(Latch::from_existing(mem)?.0),
+            Kind::SeqLock => Entry::SeqLock(SeqLock::from_existing(mem, data)?.0),
+            Kind::SharedOnce => Entry::SharedOnce(SharedOnce::from_existing(mem)?.0),
+            Kind::Data { .. } => Entry::Data(mem),
+            #[cfg(unix)]
+            Kind::NamedSemMutex => Entry::Lock(NamedSemMutex::from_existing(mem, data)?.0),
+            #[cfg(target_os = "linux")]
+            Kind::FileLock => Entry::Lock(FileLock::from_existing(mem, data)?.0),
+            #[cfg(target_os = "linux")]
+            Kind::SysVSemaphore => Entry::Lock(SysVSemaphore::from_existing(mem, data)?.0),
+        })
+    }
+}
+
+/// Initialized entry of a region
+pub enum Entry {
+    Lock(Box<dyn LockImpl>),
+    Event(Box<dyn EventImpl>),
+    Broadcast(Broadcast),
+    Condvar(Condvar),
+    EventGroup(EventGroup),
+    CountingEvent(CountingEvent),
+    Latch(Latch),
+    SeqLock(SeqLock),
+    SharedOnce(SharedOnce),
+    Data(*mut u8),
+}
+
+/// Collects the kinds of a region's entries, in order
+#[derive(Default)]
+pub struct RegionBuilder {
+    kinds: Vec<Kind>,
+}
+
+impl RegionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an entry, its index in the region is the number of entries added before it
+    pub fn entry(mut self, kind: Kind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Computes the offset of every entry
+    pub fn build(self) -> Result<Layout> {
+        let mut align = align_of::<Header>();
+        let mut used = ENTRIES;
+        let mut offsets = Vec::with_capacity(self.kinds.len());
+        for kind in &self.kinds {
+            if !kind.align().is_power_of_two() {
+                return Err(From::from(format!(
+                    "Region data alignment {} is not a power of two",
+                    kind.align()
+                )));
+            }
+            align = max_align(align, kind.align());
+            let offset = used.next_multiple_of(kind.align());
+            offsets.push(offset);
+            used = offset
+                .checked_add(kind.size())
+                .ok_or("Region does not fit in memory")?;
+        }
+
+        // FNV-1a of the kinds, offsets and sizes
+        let mut fingerprint: u64 = 0xcbf2_9ce4_8422_2325;
+        for (kind, offset) in self.kinds.iter().zip(&offsets) {
+            let sizes = [*offset as u64, kind.size() as u64];
+            let bytes =
+                std::iter::once(kind.code()).chain(sizes.iter().flat_map(|v| v.to_le_bytes()));
+            for b in bytes {
+                fingerprint = (fingerprint ^ b as u64).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+
+        Ok(Layout {
+            kinds: self.kinds,
+            offsets,
+            size: used,
+            align,
+            // 0 marks a region being initialized
+            fingerprint: fingerprint.max(1),
+        })
+    }
+}
+
+/// Offsets of a region's entries, computed by `RegionBuilder::build()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    kinds: Vec<Kind>,
+    offsets: Vec<usize>,
+    size: usize,
+    align: usize,
+    fingerprint: u64,
+}
+
+impl Layout {
+    /// Size of the region at an address aligned to `align()`
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Alignment required for the memory given to `create()` and `open()`
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Offset of entry `index` from the start of the region
+    pub fn offset(&self, index: usize) -> Option<usize> {
+        self.offsets.get(index).copied()
+    }
+
+    /// Identifies the kinds and offsets of the entries, a region can only be opened with the layout that created it
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Start of the `Data` entry following entry `index` in the region at `mem`, null if there is none
+    unsafe fn data_of(&self, mem: *mut u8, index: usize) -> *mut u8 {
+        match self.kinds.get(index + 1) {
+            Some(Kind::Data { .. }) => mem.add(self.offsets[index + 1]),
+            _ => null_mut(),
+        }
+    }
+
+    /// Initializes every entry in the provided buffer and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn create(&self, mem: *mut u8) -> Result<(Region, usize)> {
+        check_align(mem, self.align, "Region")?;
+        let header = mem as *mut Header;
+        header.write(Header {
+            fingerprint: AtomicU64::new(0),
+            entries: self.kinds.len() as u32,
+        });
+        let mut entries = Vec::with_capacity(self.kinds.len());
+        for (i, (kind, offset)) in self.kinds.iter().zip(&self.offsets).enumerate() {
+            entries.push(kind.init(mem.add(*offset), self.data_of(mem, i))?);
+        }
+        // Release pairs with the Acquire in `open()` so the entries are visible once the fingerprint is
+        (*header)
+            .fingerprint
+            .store(self.fingerprint, Ordering::Release);
+        trace!(
+            "Region create({:p}) fingerprint = {:x}",
+            mem,
+            self.fingerprint
+        );
+
+        Ok((Region { entries }, self.size))
+    }
+
+    /// Re-attaches every entry of a region created with the same layout and returns the number of used bytes
+    /// # Safety
+    /// This function is unsafe because it cannot guarantee that the provided memory is valid.
+    pub unsafe fn open(&self, mem: *mut u8) -> Result<(Region, usize)> {
+        check_align(mem, self.align, "Region")?;
+        let header = &*(mem as *const Header);
+        let fingerprint = header.fingerprint.load(Ordering::Acquire);
+        if fingerprint == 0 {
+            return Err(From::from("Region is not initialized"));
+        }
+        if fingerprint != self.fingerprint || header.entries as usize != self.kinds.len() {
+            return Err(From::from(format!(
+                "Region layout {:x} does not match the expected layout {:x}",
+                fingerprint, self.fingerprint
+            )));
+        }
+        let mut entries = Vec::with_capacity(self.kinds.len());
+        for (i, (kind, offset)) in self.kinds.iter().zip(&self.offsets).enumerate() {
+            entries.push(kind.attach(mem.add(*offset), self.data_of(mem, i))?);
+        }
+
+        Ok((Region { entries }, self.size))
+    }
+
+    /// Creates the region `offset` bytes into `map`.
+    /// The returned region borrows `map` so it cannot be used once unmapped.
+    pub fn create_in<'a, M: Mapping>(
+        &self,
+        map: &'a M,
+        offset: usize,
+    ) -> Result<Mapped<'a, Region>> {
+        let mem = mapped::at(map, offset, |_| self.size)?;
+        let region = unsafe { self.create(mem)?.0 };
+        Ok(mapped::wrap(map, region))
+    }
+
+    /// Re-attaches a region created with `create_in()` at the same offset of the same memory
+    pub fn open_in<'a, M: Mapping>(&self, map: &'a M, offset: usize) -> Result<Mapped<'a, Region>> {
+        let mem = mapped::at(map, offset, |_| self.size)?;
+        let region = unsafe { self.open(mem)?.0 };
+        Ok(mapped::wrap(map, region))
+    }
+
+    /// Places the region `offset` bytes into `shmem`.
+    /// The owner of `shmem` creates it and other processes open it.
+    #[cfg(feature = "shared_memory")]
+    pub fn in_shmem<'a>(
+        &self,
+        shmem: &'a shared_memory::Shmem,
+        offset: usize,
+    ) -> Result<Mapped<'a, Region>> {
+        if shmem.is_owner() {
+            self.create_in(shmem, offset)
+        } else {
+            self.open_in(shmem, offset)
+        }
+    }
+}
+
+/// Entries of a region, in the order they were added to the builder
+pub struct Region {
+    entries: Vec<Entry>,
+}
+
+impl Region {
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Entry> {
+        self.entries.get(index)
+    }
+
+    /// Lock at `index`, None if the entry is not a lock. `SeqLock` entries are only returned by `get()`
+    pub fn lock(&self, index: usize) -> Option<&dyn LockImpl> {
+        match self.entries.get(index)? {
+            Entry::Lock(lock) => Some(lock.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Event at `index`, None if the entry is not an `Event` or `BusyEvent`
+    pub fn event(&self, index: usize) -> Option<&dyn EventImpl> {
+        match self.entries.get(index)? {
+            Entry::Event(event) => Some(event.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Start of the data block at `index`, None if the entry is not `Data`
+    pub fn data(&self, index: usize) -> Option<*mut u8> {
+        match self.entries.get(index)? {
+            Entry::Data(ptr) => Some(*ptr),
+            _ => None,
+        }
+    }
+
+    /// Takes ownership of the entries
+    pub fn into_entries(self) -> Vec<Entry> {
+        self.entries
+    }
+}
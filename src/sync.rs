@@ -0,0 +1,16 @@
+//! Atomics used by the pure-Rust primitives. Building with `--cfg loom` swaps them for
+//! [loom](https://docs.rs/loom)'s types so their memory orderings can be model checked.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicU8, Ordering};
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+/// Called on every iteration of a busy loop
+#[inline]
+pub(crate) fn spin_loop() {
+    #[cfg(loom)]
+    loom::thread::yield_now();
+    #[cfg(not(loom))]
+    std::hint::spin_loop();
+}
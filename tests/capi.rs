@@ -0,0 +1,136 @@
+//! Checks that C programs using include/raw_sync.h share primitives with Rust
+#![cfg(all(unix, feature = "capi"))]
+
+use std::fs::OpenOptions;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr::null_mut;
+use std::time::Duration;
+
+use raw_sync::events::*;
+use raw_sync::locks::*;
+use raw_sync::Timeout;
+
+const MAP_SIZE: usize = 8192;
+const DATA_OFFSET: usize = 4096;
+const INCREMENTS: u64 = 1000;
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Builds the library as a cdylib with the `capi` feature and returns its directory. It goes
+/// to its own target directory so it does not invalidate the regular builds.
+fn lib_dir() -> PathBuf {
+    let target_dir = root().join("target").join("capi");
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .current_dir(root())
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "capi",
+            "--crate-type",
+            "cdylib",
+        ])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the cdylib");
+    target_dir.join("debug")
+}
+
+fn compile(src: &Path, out: &Path) {
+    let lib_dir = lib_dir();
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(root().join("include"))
+        .arg(src)
+        .arg("-o")
+        .arg(out)
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lraw_sync")
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "failed to compile {}", src.display());
+}
+
+#[test]
+fn header_is_up_to_date() {
+    // Generated by build.rs from the signatures in src/capi.rs
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/raw_sync.h"));
+    let header = std::fs::read_to_string(root().join("include/raw_sync.h")).unwrap();
+    assert!(
+        header == generated,
+        "include/raw_sync.h is out of date, replace it with {}/raw_sync.h",
+        env!("OUT_DIR")
+    );
+}
+
+#[test]
+fn c_program_shares_mutex_and_events() {
+    let dir = std::env::temp_dir();
+    let exe = dir.join(format!("raw_sync_interop_{}", std::process::id()));
+    compile(&root().join("tests/capi/interop.c"), &exe);
+
+    let path = dir.join(format!("raw_sync_interop_{}.map", std::process::id()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    file.set_len(MAP_SIZE as u64).unwrap();
+    let base = unsafe {
+        libc::mmap(
+            null_mut(),
+            MAP_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    assert_ne!(base, libc::MAP_FAILED);
+    let base = base as *mut u8;
+    let counter = unsafe { base.add(DATA_OFFSET) };
+
+    let (mutex, used) = unsafe { Mutex::new(base, counter).unwrap() };
+    let mut offset = used;
+    let (to_rust, used) = unsafe { Event::new(base.add(offset), true).unwrap() };
+    offset += used;
+    let (to_c, _) = unsafe { Event::new(base.add(offset), true).unwrap() };
+
+    // Only load the library built above
+    let mut child = Command::new(&exe)
+        .arg(&path)
+        .args([Mutex::SIZE, Mutex::ALIGN, Event::SIZE, Event::ALIGN].map(|v| v.to_string()))
+        .env_remove("LD_LIBRARY_PATH")
+        .env_remove("DYLD_LIBRARY_PATH")
+        .spawn()
+        .unwrap();
+    for _ in 0..INCREMENTS {
+        let guard = mutex.lock().unwrap();
+        unsafe { *(*guard as *mut u64) += 1 };
+    }
+    to_rust.wait(Timeout::Val(Duration::from_secs(5))).unwrap();
+    assert_eq!(unsafe { *(counter as *mut u64) }, 2 * INCREMENTS);
+
+    // The C side must time out while we hold the mutex
+    let guard = mutex.lock().unwrap();
+    to_c.set(EventState::Signaled).unwrap();
+    to_rust.wait(Timeout::Val(Duration::from_secs(5))).unwrap();
+    drop(guard);
+
+    assert!(child.wait().unwrap().success());
+    unsafe { libc::munmap(base as _, MAP_SIZE) };
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&exe);
+}
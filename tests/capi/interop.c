@@ -0,0 +1,90 @@
+/*
+ * Opens the primitives created by tests/capi.rs in the file given as argument :
+ * a mutex protecting a counter followed by two auto reset events. The mutex and event
+ * sizes and alignments seen from Rust follow it.
+ */
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+#include "raw_sync.h"
+
+#define MAP_SIZE 8192
+#define DATA_OFFSET 4096
+#define INCREMENTS 1000
+
+static int fail(const char *what) {
+    const char *err = raw_sync_last_error();
+    fprintf(stderr, "%s : %s\n", what, err ? err : "no error message");
+    return 1;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 6) {
+        fprintf(stderr, "usage : %s <mapping file> <mutex size> <mutex align> <event size> <event align>\n", argv[0]);
+        return 2;
+    }
+    if (raw_sync_mutex_size_of(NULL) != strtoul(argv[2], NULL, 10) ||
+        raw_sync_mutex_align() != strtoul(argv[3], NULL, 10) ||
+        raw_sync_event_size_of(NULL) != strtoul(argv[4], NULL, 10) ||
+        raw_sync_event_align() != strtoul(argv[5], NULL, 10)) {
+        fprintf(stderr, "sizes or alignments differ from Rust\n");
+        return 1;
+    }
+    int fd = open(argv[1], O_RDWR);
+    if (fd < 0) {
+        perror("open");
+        return 1;
+    }
+    unsigned char *base = mmap(NULL, MAP_SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    if (base == MAP_FAILED) {
+        perror("mmap");
+        return 1;
+    }
+
+    RawSyncMutex *mutex;
+    RawSyncEvent *to_rust, *to_c;
+    size_t used, offset;
+    if (raw_sync_mutex_from_existing(base, base + DATA_OFFSET, &mutex, &used))
+        return fail("raw_sync_mutex_from_existing");
+    offset = used;
+    if (raw_sync_event_from_existing(base + offset, &to_rust, &used))
+        return fail("raw_sync_event_from_existing");
+    offset += used;
+    if (raw_sync_event_from_existing(base + offset, &to_c, &used))
+        return fail("raw_sync_event_from_existing");
+
+    uint64_t *counter = raw_sync_mutex_data(mutex);
+    for (int i = 0; i < INCREMENTS; i++) {
+        if (raw_sync_mutex_lock(mutex))
+            return fail("raw_sync_mutex_lock");
+        *counter += 1;
+        if (raw_sync_mutex_unlock(mutex))
+            return fail("raw_sync_mutex_unlock");
+    }
+    if (raw_sync_event_set(to_rust, true))
+        return fail("raw_sync_event_set");
+
+    /* Rust holds the mutex before waking us up */
+    if (raw_sync_event_wait(to_c, 5000))
+        return fail("raw_sync_event_wait");
+    if (raw_sync_mutex_timed_lock(mutex, 50) == 0) {
+        fprintf(stderr, "acquired a mutex held by another process\n");
+        return 1;
+    }
+    if (raw_sync_last_error() == NULL) {
+        fprintf(stderr, "timed out without an error message\n");
+        return 1;
+    }
+    if (raw_sync_event_set(to_rust, true))
+        return fail("raw_sync_event_set");
+
+    raw_sync_event_free(to_c);
+    raw_sync_event_free(to_rust);
+    raw_sync_mutex_free(mutex);
+    munmap(base, MAP_SIZE);
+    close(fd);
+    return 0;
+}
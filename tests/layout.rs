@@ -0,0 +1,398 @@
+//! Checks the shared memory layout documented in LAYOUT.md by inspecting the bytes written
+//! by each primitive. A failure here means processes built from different versions of the
+//! crate would disagree on the same memory.
+
+use std::mem::size_of;
+use std::ptr::null_mut;
+
+use raw_sync::channel::{mpmc, spsc};
+use raw_sync::events::*;
+use raw_sync::locks::*;
+use raw_sync::once::SharedOnce;
+use raw_sync::Timeout;
+
+/// 8 byte aligned buffer
+struct Buf(Vec<u64>);
+impl Buf {
+    fn new() -> Self {
+        Self(vec![0; 1024])
+    }
+    fn ptr(&mut self) -> *mut u8 {
+        self.0.as_mut_ptr() as *mut u8
+    }
+    fn u8_at(&mut self, offset: usize) -> u8 {
+        unsafe { *self.ptr().add(offset) }
+    }
+    fn u32_at(&mut self, offset: usize) -> u32 {
+        unsafe { (self.ptr().add(offset) as *const u32).read_unaligned() }
+    }
+    fn u64_at(&mut self, offset: usize) -> u64 {
+        unsafe { (self.ptr().add(offset) as *const u64).read_unaligned() }
+    }
+}
+
+#[cfg(unix)]
+mod os {
+    /// ABI tag written before every pthread object
+    pub const TAG: usize = 8;
+    pub const PTHREAD_MUTEX: usize = std::mem::size_of::<libc::pthread_mutex_t>();
+    pub const PTHREAD_COND: usize = std::mem::size_of::<libc::pthread_cond_t>();
+    pub const PTHREAD_RWLOCK: usize = std::mem::size_of::<libc::pthread_rwlock_t>();
+    pub const MUTEX: usize = TAG + PTHREAD_MUTEX;
+}
+#[cfg(windows)]
+mod os {
+    pub const MUTEX: usize = 4;
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn pthread_sizes() {
+    assert_eq!(os::PTHREAD_MUTEX, 40);
+    assert_eq!(os::PTHREAD_RWLOCK, 56);
+    assert_eq!(os::PTHREAD_COND, 48);
+}
+
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+#[test]
+fn pthread_sizes() {
+    assert_eq!(os::PTHREAD_MUTEX, 64);
+    assert_eq!(os::PTHREAD_RWLOCK, 200);
+    assert_eq!(os::PTHREAD_COND, 48);
+}
+
+#[test]
+fn lock_sizes() {
+    assert_eq!(Mutex::size_of(None), os::MUTEX);
+    #[cfg(unix)]
+    {
+        assert_eq!(RwLock::size_of(None), os::TAG + os::PTHREAD_RWLOCK);
+        assert_eq!(NamedSemMutex::size_of(None), 4);
+    }
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(<FileLock as LockInit>::size_of(None), 256);
+        assert_eq!(SysVSemaphore::size_of(None), 4);
+    }
+    assert_eq!(Poison::<Mutex>::size_of(None), os::MUTEX + 4);
+    assert_eq!(SeqLock::size_of(None), os::MUTEX + 4);
+    assert_eq!(
+        LockRegistry::size_of(None),
+        os::MUTEX + (REGISTRY_MAX_HOLDS + REGISTRY_MAX_WAITS) * 16
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn event_sizes() {
+    assert_eq!(Event::size_of(None), os::MUTEX + os::PTHREAD_COND + 8);
+    assert_eq!(BusyEvent::size_of(None), 2);
+    assert_eq!(Broadcast::size_of(None), os::MUTEX + os::PTHREAD_COND + 8);
+    assert_eq!(Condvar::size_of(None), os::TAG + os::PTHREAD_COND);
+    assert_eq!(SharedOnce::size_of(None), 8);
+}
+
+#[cfg(windows)]
+#[test]
+fn event_sizes() {
+    assert_eq!(Event::size_of(None), 4);
+    assert_eq!(BusyEvent::size_of(None), 2);
+    assert_eq!(Broadcast::size_of(None), 4 + 16);
+    assert_eq!(Condvar::size_of(None), 4 + 8);
+    assert_eq!(SharedOnce::size_of(None), 8);
+}
+
+#[test]
+fn busy_event_fields() {
+    let mut buf = Buf::new();
+    let (event, used) = unsafe { BusyEvent::new(buf.ptr(), true).unwrap() };
+    assert_eq!(used, 2);
+    assert_eq!((buf.u8_at(0), buf.u8_at(1)), (0, 1));
+    event.set(EventState::Signaled).unwrap();
+    assert_eq!((buf.u8_at(0), buf.u8_at(1)), (1, 1));
+}
+
+#[cfg(unix)]
+#[test]
+fn event_fields() {
+    let mut buf = Buf::new();
+    let (event, used) = unsafe { Event::new(buf.ptr(), false).unwrap() };
+    assert_eq!(used, Event::size_of(None));
+    let auto_reset = os::MUTEX + os::PTHREAD_COND;
+    assert_eq!((buf.u8_at(auto_reset), buf.u8_at(auto_reset + 1)), (0, 0));
+    event.set(EventState::Signaled).unwrap();
+    assert_eq!((buf.u8_at(auto_reset), buf.u8_at(auto_reset + 1)), (0, 1));
+}
+
+#[cfg(unix)]
+#[test]
+fn broadcast_fields() {
+    let mut buf = Buf::new();
+    let (broadcast, _) = unsafe { Broadcast::new(buf.ptr()).unwrap() };
+    broadcast.notify().unwrap();
+    broadcast.notify().unwrap();
+    assert_eq!(buf.u64_at(os::MUTEX + os::PTHREAD_COND), 2);
+}
+
+#[test]
+fn lock_flags_follow_the_mutex() {
+    let mut buf = Buf::new();
+    let (lock, used) = unsafe { Poison::<Mutex>::new(buf.ptr(), null_mut()).unwrap() };
+    assert_eq!(used, os::MUTEX + 4);
+    lock.poison();
+    assert_eq!(buf.u32_at(os::MUTEX), 1);
+    drop(lock);
+
+    let mut buf = Buf::new();
+    let (lock, used) = unsafe { SeqLock::new(buf.ptr(), null_mut()).unwrap() };
+    assert_eq!(used, os::MUTEX + 4);
+    drop(lock.write().unwrap());
+    assert_eq!(buf.u32_at(os::MUTEX), 2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn file_lock_stores_its_range() {
+    let mut buf = Buf::new();
+    let (lock, used) = unsafe { FileLock::with_range(buf.ptr(), 16..48, null_mut()).unwrap() };
+    assert_eq!(used, 256);
+    assert_eq!((buf.u64_at(0), buf.u64_at(8)), (16, 32));
+    assert_eq!(buf.u8_at(16), b'/');
+
+    // Bounds past i64::MAX would wrap in the off_t given to fcntl()
+    let past = i64::MAX as u64 + 1;
+    unsafe { (buf.ptr() as *mut u64).write(past) };
+    assert!(unsafe { FileLock::attach(buf.ptr(), null_mut()) }.is_err());
+    let mut other = Buf::new();
+    assert!(unsafe { FileLock::with_range(other.ptr(), 0..past, null_mut()) }.is_err());
+    let path = std::path::Path::new("/nonexistent/raw_sync.lock");
+    let err = FileLock::open(path, past..past, null_mut()).err().unwrap();
+    assert!(err.to_string().contains("file offset"), "{}", err);
+    drop(lock);
+}
+
+#[test]
+fn counters_follow_the_condvar() {
+    let mut buf = Buf::new();
+    let (group, used) = unsafe { EventGroup::new(buf.ptr()).unwrap() };
+    assert_eq!(used, EventGroup::size_of(None));
+    group.set_bits(0xA5).unwrap();
+    assert_eq!(buf.u32_at(used - size_of::<u32>()), 0xA5);
+
+    let mut buf = Buf::new();
+    let (event, used) = unsafe { CountingEvent::new(buf.ptr()).unwrap() };
+    event.signal_n(3).unwrap();
+    assert_eq!(buf.u32_at(used - size_of::<u32>()), 3);
+}
+
+#[test]
+fn latch_and_once_fields() {
+    let mut buf = Buf::new();
+    let (latch, _) = unsafe { Latch::new(buf.ptr(), 3).unwrap() };
+    latch.count_down(1).unwrap();
+    assert_eq!(buf.u32_at(0), 2);
+
+    let mut buf = Buf::new();
+    let (once, used) = unsafe { SharedOnce::new(buf.ptr()).unwrap() };
+    assert_eq!((used, buf.u64_at(0)), (8, 0));
+    once.call_once(|| {});
+    assert_eq!(buf.u64_at(0), 2);
+}
+
+#[test]
+fn channel_headers() {
+    let mut buf = Buf::new();
+    let (sender, used) = unsafe { spsc::Sender::new(buf.ptr(), 64).unwrap() };
+    assert_eq!(used, 16 + 2 * Event::size_of(None) + 64);
+    sender.send(b"abc", Timeout::Infinite).unwrap();
+    // capacity, head, tail, used
+    let header: Vec<u32> = (0..4).map(|i| buf.u32_at(i * 4)).collect();
+    assert_eq!(header, [64, 0, 7, 7]);
+
+    let mut buf = Buf::new();
+    let (queue, used) = unsafe { mpmc::Queue::new(buf.ptr(), 4, 8).unwrap() };
+    assert_eq!(
+        used,
+        16 + Mutex::size_of(None) + 2 * Event::size_of(None) + 4 * (4 + 8)
+    );
+    queue.push(b"abc", Timeout::Infinite).unwrap();
+    // capacity, slot size, head, len
+    let header: Vec<u32> = (0..4).map(|i| buf.u32_at(i * 4)).collect();
+    assert_eq!(header, [4, 8, 0, 1]);
+}
+
+#[cfg(unix)]
+#[test]
+fn abi_tag_describes_the_creator() {
+    let mut buf = Buf::new();
+    let (lock, _) = unsafe { Mutex::new(buf.ptr(), null_mut()).unwrap() };
+    drop(lock);
+    // kind, pointer width, big endian, C library, size of the pthread object
+    let width = usize::BITS as u8;
+    let big_endian = cfg!(target_endian = "big") as u8;
+    assert_eq!(&buf.0[0].to_ne_bytes()[..3], &[1, width, big_endian]);
+    assert_eq!(
+        buf.u32_at(4),
+        u32::from_le(os::PTHREAD_MUTEX as u32),
+        "size is stored little endian"
+    );
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    assert_eq!(buf.u8_at(3), 1);
+
+    // Opened as another kind
+    let err = unsafe { Condvar::from_existing(buf.ptr()) }.err().unwrap();
+    assert!(err.to_string().contains("does not hold"), "{}", err);
+
+    // Created by a process of another pointer width
+    unsafe { *buf.ptr().add(1) = if width == 64 { 32 } else { 64 } };
+    let err = unsafe { Mutex::from_existing(buf.ptr(), null_mut()) }
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(
+        err.contains(&format!("this process is {}-bit", width)),
+        "{}",
+        err
+    );
+    let event = unsafe { Event::from_existing(buf.ptr()) };
+    assert!(event.is_err(), "an event reused the incompatible mutex");
+
+    // Never initialized
+    let mut buf = Buf::new();
+    assert!(unsafe { Mutex::from_existing(buf.ptr(), null_mut()) }.is_err());
+}
+
+#[test]
+fn used_bytes_match_const_sizes() {
+    let mut buf = Buf::new();
+    let mem = buf.ptr();
+    unsafe {
+        assert_eq!(Mutex::new(mem, null_mut()).unwrap().1, Mutex::SIZE);
+        assert_eq!(
+            Poison::<Mutex>::new(mem, null_mut()).unwrap().1,
+            Poison::<Mutex>::SIZE
+        );
+        assert_eq!(SeqLock::new(mem, null_mut()).unwrap().1, SeqLock::SIZE);
+        assert_eq!(Event::new(mem, true).unwrap().1, Event::SIZE);
+        assert_eq!(BusyEvent::new(mem, true).unwrap().1, BusyEvent::SIZE);
+        assert_eq!(Broadcast::new(mem).unwrap().1, Broadcast::SIZE);
+        assert_eq!(Condvar::new(mem).unwrap().1, Condvar::SIZE);
+        assert_eq!(EventGroup::new(mem).unwrap().1, EventGroup::SIZE);
+        assert_eq!(CountingEvent::new(mem).unwrap().1, CountingEvent::SIZE);
+        assert_eq!(Latch::new(mem, 1).unwrap().1, Latch::SIZE);
+        assert_eq!(SharedOnce::new(mem).unwrap().1, SharedOnce::SIZE);
+        assert_eq!(
+            spsc::Sender::new(mem, 64).unwrap().1,
+            spsc::Sender::size_for(64)
+        );
+        assert_eq!(
+            mpmc::Queue::new(mem, 4, 8).unwrap().1,
+            mpmc::Queue::size_for(4, 8)
+        );
+    }
+    let mut buf = vec![0u64; LockRegistry::SIZE / 8 + 1];
+    let (_, used) = unsafe { LockRegistry::new(buf.as_mut_ptr() as *mut u8).unwrap() };
+    assert_eq!(used, LockRegistry::SIZE);
+}
+
+#[test]
+fn misaligned_memory_is_rejected() {
+    let mut buf = Buf::new();
+    let mem = unsafe { buf.ptr().add(1) };
+    let errors = unsafe {
+        [
+            Mutex::new(mem, null_mut()).err(),
+            SeqLock::new(mem, null_mut()).err(),
+            Event::new(mem, false).err(),
+            Latch::new(mem, 1).err(),
+            SharedOnce::new(mem).err(),
+            spsc::Sender::new(mem, 64).err(),
+            mpmc::Queue::new(mem, 4, 8).err(),
+        ]
+    };
+    for err in errors {
+        let err = err.expect("misaligned memory was accepted");
+        assert!(err.to_string().contains("must be aligned"), "{}", err);
+    }
+    // Nothing was written
+    assert!(buf.0.iter().all(|&b| b == 0));
+    assert_eq!(Mutex::size_of(Some(mem)), Mutex::ALIGN - 1 + Mutex::SIZE);
+}
+
+/// A layout declared at compile time from the constants
+#[repr(C, align(8))]
+struct Shared {
+    lock: [u8; SeqLock::SIZE.next_multiple_of(8)],
+    ready: [u8; Event::SIZE.next_multiple_of(8)],
+    once: [u8; SharedOnce::SIZE],
+}
+const _: () = assert!(SeqLock::ALIGN <= 8 && Event::ALIGN <= 8 && SharedOnce::ALIGN <= 8);
+
+#[test]
+fn static_layout_from_consts() {
+    let mut shared = Box::new(Shared {
+        lock: [0; SeqLock::SIZE.next_multiple_of(8)],
+        ready: [0; Event::SIZE.next_multiple_of(8)],
+        once: [0; SharedOnce::SIZE],
+    });
+    unsafe {
+        SeqLock::new(shared.lock.as_mut_ptr(), null_mut()).unwrap();
+        Event::new(shared.ready.as_mut_ptr(), false).unwrap();
+        SharedOnce::new(shared.once.as_mut_ptr()).unwrap();
+        let (event, _) = Event::from_existing(shared.ready.as_mut_ptr()).unwrap();
+        event.set(EventState::Signaled).unwrap();
+        event.wait(Timeout::Infinite).unwrap();
+    }
+}
+
+#[test]
+fn region_header_and_offsets() {
+    use raw_sync::region::{Kind, RegionBuilder};
+
+    let layout = RegionBuilder::new()
+        .entry(Kind::BusyEvent { auto_reset: true })
+        .entry(Kind::SharedOnce)
+        .build()
+        .unwrap();
+    assert_eq!(layout.offset(0), Some(16));
+    assert_eq!(layout.offset(1), Some(24));
+    assert_eq!(layout.size(), 24 + SharedOnce::SIZE);
+
+    let mut buf = Buf::new();
+    unsafe { layout.create(buf.ptr()).unwrap() };
+    assert_eq!(buf.u64_at(0), layout.fingerprint());
+    assert_eq!(buf.u32_at(8), 2);
+    assert_eq!((buf.u8_at(16), buf.u8_at(17)), (0, 1));
+}
+
+#[test]
+fn region_locks_protect_the_next_data_entry() {
+    use raw_sync::region::{Entry, Kind, RegionBuilder};
+
+    let data = Kind::Data { size: 8, align: 8 };
+    #[allow(unused_mut)]
+    let mut kinds = [Kind::Mutex, data, Kind::SeqLock, data].to_vec();
+    #[cfg(unix)]
+    kinds.extend([Kind::NamedSemMutex, data]);
+    #[cfg(target_os = "linux")]
+    kinds.extend([Kind::FileLock, data, Kind::SysVSemaphore, data]);
+    let layout = kinds
+        .iter()
+        .fold(RegionBuilder::new(), |b, k| b.entry(*k))
+        .entry(Kind::Mutex)
+        .build()
+        .unwrap();
+
+    let mut buf = Buf::new();
+    assert!(layout.size() <= 8 * 1024);
+    let (region, _) = unsafe { layout.create(buf.ptr()).unwrap() };
+    for i in (0..kinds.len()).step_by(2) {
+        let guard = match region.get(i) {
+            Some(Entry::SeqLock(seqlock)) => seqlock.write().unwrap(),
+            _ => region.lock(i).unwrap().lock().unwrap(),
+        };
+        assert_eq!(*guard, region.data(i + 1).unwrap(), "entry {}", i);
+    }
+    // Nothing follows the last mutex
+    assert!(region.lock(kinds.len()).unwrap().lock().unwrap().is_null());
+}
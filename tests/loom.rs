@@ -0,0 +1,162 @@
+//! Model checks the memory orderings of the atomic based primitives.
+//!
+//! Run with : `RUSTFLAGS="--cfg loom" cargo test --release --test loom`
+#![cfg(loom)]
+
+use loom::cell::UnsafeCell;
+use loom::sync::atomic::{AtomicU32, Ordering};
+use loom::sync::Arc;
+use loom::thread;
+
+use raw_sync::events::*;
+use raw_sync::locks::SeqLock;
+use raw_sync::Timeout;
+
+/// Memory shared by the threads of one model execution
+struct Shared {
+    event_mem: Box<[u64; 4]>,
+    data: UnsafeCell<usize>,
+}
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            event_mem: Box::new([0; 4]),
+            data: UnsafeCell::new(0),
+        })
+    }
+    fn mem(&self) -> *mut u8 {
+        self.event_mem.as_ptr() as *mut u8
+    }
+}
+
+#[test]
+fn busy_auto_reset_publishes_data() {
+    loom::model(|| {
+        let shared = Shared::new();
+        let (event, _) = unsafe { BusyEvent::new(shared.mem(), true).unwrap() };
+
+        let consumer = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let (event, _) = unsafe { BusyEvent::from_existing(shared.mem()).unwrap() };
+                event.wait(Timeout::Infinite).unwrap();
+                let val = shared.data.with(|v| unsafe { *v });
+                assert_eq!(val, 42);
+                // The signal was consumed by the wait above
+                assert!(event.wait(Timeout::Val(Default::default())).is_err());
+            })
+        };
+
+        shared.data.with_mut(|v| unsafe { *v = 42 });
+        event.set(EventState::Signaled).unwrap();
+
+        consumer.join().unwrap();
+    });
+}
+
+#[test]
+fn busy_manual_reset_wakes_every_waiter() {
+    loom::model(|| {
+        let shared = Shared::new();
+        let (event, _) = unsafe { BusyEvent::new(shared.mem(), false).unwrap() };
+
+        let waiter = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let (event, _) = unsafe { BusyEvent::from_existing(shared.mem()).unwrap() };
+                event.wait(Timeout::Infinite).unwrap();
+                let val = shared.data.with(|v| unsafe { *v });
+                assert_eq!(val, 7);
+            })
+        };
+
+        shared.data.with_mut(|v| unsafe { *v = 7 });
+        event.set(EventState::Signaled).unwrap();
+        // Manual reset events stay signaled for the other waiters
+        event.wait(Timeout::Infinite).unwrap();
+
+        waiter.join().unwrap();
+        event.wait(Timeout::Val(Default::default())).unwrap();
+    });
+}
+
+#[test]
+fn busy_clear_blocks_next_wait() {
+    loom::model(|| {
+        let shared = Shared::new();
+        let (event, _) = unsafe { BusyEvent::new(shared.mem(), false).unwrap() };
+
+        event.set(EventState::Signaled).unwrap();
+        event.set(EventState::Clear).unwrap();
+
+        let waiter = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let (event, _) = unsafe { BusyEvent::from_existing(shared.mem()).unwrap() };
+                assert!(event.wait(Timeout::Val(Default::default())).is_err());
+            })
+        };
+        waiter.join().unwrap();
+    });
+}
+
+/// Data of a `SeqLock`, relaxed atomics so loom reports reads racing with writes
+struct SeqShared {
+    lock_mem: Box<[u64; 32]>,
+    words: [AtomicU32; 2],
+}
+unsafe impl Send for SeqShared {}
+unsafe impl Sync for SeqShared {}
+
+impl SeqShared {
+    fn mem(&self) -> *mut u8 {
+        self.lock_mem.as_ptr() as *mut u8
+    }
+    fn data(&self) -> *mut u8 {
+        self.words.as_ptr() as *mut u8
+    }
+}
+
+fn words(data: *const u8) -> &'static [AtomicU32; 2] {
+    unsafe { &*(data as *const [AtomicU32; 2]) }
+}
+
+#[test]
+fn seqlock_reads_retry_until_consistent() {
+    loom::model(|| {
+        let shared = Arc::new(SeqShared {
+            lock_mem: Box::new([0; 32]),
+            words: [AtomicU32::new(0), AtomicU32::new(0)],
+        });
+        assert!(SeqLock::SIZE <= 32 * 8);
+        let (seqlock, _) = unsafe { SeqLock::new(shared.mem(), shared.data()).unwrap() };
+
+        let writer = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let (seqlock, _) =
+                    unsafe { SeqLock::from_existing(shared.mem(), shared.data()).unwrap() };
+                for v in 1..=2 {
+                    let guard = seqlock.write().unwrap();
+                    for word in words(*guard) {
+                        word.store(v, Ordering::Relaxed);
+                    }
+                }
+            })
+        };
+
+        let (a, b) = seqlock.read(|data| {
+            let w = words(data);
+            (w[0].load(Ordering::Relaxed), w[1].load(Ordering::Relaxed))
+        });
+        assert_eq!(a, b, "torn read");
+
+        writer.join().unwrap();
+        assert_eq!(seqlock.sequence(), 4);
+        let last = seqlock.read(|data| words(data)[1].load(Ordering::Relaxed));
+        assert_eq!(last, 2);
+    });
+}
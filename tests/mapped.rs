@@ -0,0 +1,102 @@
+//! Places primitives in mappings through `create_in()`/`open_in()`
+
+use std::cell::UnsafeCell;
+
+use raw_sync::events::*;
+use raw_sync::locks::*;
+use raw_sync::mapped::Mapping;
+use raw_sync::Timeout;
+
+/// Mapping over an 8 byte aligned heap buffer, `UnsafeCell` allows writing through `&self`
+struct Heap(Box<UnsafeCell<[u64; 64]>>);
+unsafe impl Mapping for Heap {
+    fn as_mut_ptr(&self) -> *mut u8 {
+        self.0.get() as *mut u8
+    }
+    fn len(&self) -> usize {
+        std::mem::size_of::<[u64; 64]>()
+    }
+}
+
+#[test]
+fn primitives_are_bounds_checked() {
+    let map = Heap(Box::new(UnsafeCell::new([0; 64])));
+    let lock = Mutex::create_in(&map, 0, 16).unwrap();
+    let mut guard = lock.lock().unwrap();
+    unsafe { **guard = 7 };
+    drop(guard);
+    let other = Mutex::open_in(&map, 0, 16).unwrap();
+    assert_eq!(unsafe { **other.lock().unwrap() }, 7);
+
+    let event = Event::create_in(&map, 256, false).unwrap();
+    event.set(EventState::Signaled).unwrap();
+    Event::open_in(&map, 256)
+        .unwrap()
+        .wait(Timeout::Infinite)
+        .unwrap();
+
+    for offset in [map.len() - 8, map.len() + 8, usize::MAX] {
+        let err = Event::create_in(&map, offset, false).err().unwrap();
+        assert!(err.to_string().contains("mapping"), "{}", err);
+    }
+    for data_size in [map.len(), usize::MAX] {
+        let err = Mutex::create_in(&map, 0, data_size).err().unwrap();
+        assert!(err.to_string().contains("does not fit"), "{}", err);
+        let err = Mutex::open_in(&map, 0, data_size).err().unwrap();
+        assert!(err.to_string().contains("does not fit"), "{}", err);
+    }
+    assert!(Mutex::create_in(&map, 1, 0).is_err(), "misaligned offset");
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn memmap2_raw_mapping() {
+    use raw_sync::region::{Kind, RegionBuilder};
+
+    let map = memmap2::MmapRaw::from(memmap2::MmapMut::map_anon(4096).unwrap());
+    let layout = RegionBuilder::new()
+        .entry(Kind::Mutex)
+        .entry(Kind::Data { size: 8, align: 8 })
+        .build()
+        .unwrap();
+    let region = layout.create_in(&map, 64).unwrap();
+    let data = region.data(1).unwrap();
+    assert_eq!(
+        data as usize - map.as_mut_ptr() as usize,
+        64 + layout.offset(1).unwrap()
+    );
+
+    let reopened = layout.open_in(&map, 64).unwrap();
+    drop(reopened.lock(0).unwrap().lock().unwrap());
+    assert!(layout.open_in(&map, 4096 - 8).is_err());
+}
+
+#[cfg(feature = "shared_memory")]
+#[test]
+fn shared_memory_owner_creates_and_others_open() {
+    use raw_sync::region::{Kind, RegionBuilder};
+    use shared_memory::ShmemConf;
+
+    let owner = ShmemConf::new().size(4096).create().unwrap();
+    let other = ShmemConf::new().os_id(owner.get_os_id()).open().unwrap();
+    assert!(owner.is_owner() && !other.is_owner());
+
+    let lock = Mutex::in_shmem(&owner, 0, 8).unwrap();
+    unsafe { **lock.lock().unwrap() = 42 };
+    let opened = Mutex::in_shmem(&other, 0, 8).unwrap();
+    assert_eq!(unsafe { **opened.lock().unwrap() }, 42);
+
+    let event = Event::in_shmem(&owner, 512, true).unwrap();
+    event.set(EventState::Signaled).unwrap();
+    let opened = Event::in_shmem(&other, 512, true).unwrap();
+    opened.wait(Timeout::Infinite).unwrap();
+
+    let layout = RegionBuilder::new()
+        .entry(Kind::CountingEvent)
+        .build()
+        .unwrap();
+    let region = layout.in_shmem(&owner, 1024).unwrap();
+    assert_eq!(region.len(), 1);
+    assert!(layout.in_shmem(&other, 1024).is_ok());
+    assert!(Mutex::in_shmem(&other, 4096 - 8, 0).is_err());
+}
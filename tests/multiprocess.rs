@@ -0,0 +1,1459 @@
+//! Runs every lock and event implementation across real processes. Each scenario maps
+//! anonymous `MAP_SHARED` memory, forks children that re-open the primitive with
+//! `from_existing` and asserts on the shared state and on the children's exit codes.
+#![cfg(unix)]
+
+use std::mem::size_of;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use raw_sync::events::*;
+use raw_sync::locks::*;
+use raw_sync::once::SharedOnce;
+use raw_sync::Timeout;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Forking while another test thread holds a libc or std lock could deadlock the child
+static FORK_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+const MAP_SIZE: usize = 64 * 1024;
+/// Offset of the primitive in the mapping
+const PRIM_OFFSET: usize = 0;
+/// Offset of the data protected by the primitive
+const DATA_OFFSET: usize = 32 * 1024;
+/// Offset of the flags used by the scenarios to coordinate
+const FLAGS_OFFSET: usize = 48 * 1024;
+
+struct SharedMem {
+    ptr: *mut u8,
+}
+impl SharedMem {
+    fn new() -> Self {
+        let ptr = unsafe {
+            libc::mmap(
+                null_mut(),
+                MAP_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(ptr, libc::MAP_FAILED, "mmap failed");
+        Self {
+            ptr: ptr as *mut u8,
+        }
+    }
+    fn prim(&self) -> *mut u8 {
+        unsafe { self.ptr.add(PRIM_OFFSET) }
+    }
+    fn data(&self) -> *mut u8 {
+        unsafe { self.ptr.add(DATA_OFFSET) }
+    }
+    fn counter(&self) -> *mut usize {
+        self.data() as *mut usize
+    }
+    fn flag(&self, idx: usize) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(FLAGS_OFFSET) as *const AtomicU32).add(idx) }
+    }
+    fn wait_flag(&self, idx: usize, val: u32) {
+        let start = Instant::now();
+        while self.flag(idx).load(Ordering::Acquire) != val {
+            assert!(start.elapsed() < Duration::from_secs(10), "flag never set");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+impl Drop for SharedMem {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as _, MAP_SIZE) };
+    }
+}
+
+/// Runs `f` in a forked child. The child exits with 0 on success and 101 if `f` panics
+fn fork_child<F: FnOnce()>(f: F) -> libc::pid_t {
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        let code = match catch_unwind(AssertUnwindSafe(f)) {
+            Ok(()) => 0,
+            Err(_) => 101,
+        };
+        unsafe { libc::_exit(code) };
+    }
+    pid
+}
+
+/// Waits for a child and returns its exit code, or the negated signal that killed it
+fn wait_child(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+    let res = unsafe { libc::waitpid(pid, &mut status, 0) };
+    assert_eq!(res, pid, "waitpid failed");
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else {
+        -libc::WTERMSIG(status)
+    }
+}
+
+/// Returns true once the child has exited, without blocking
+fn child_exited(pid: libc::pid_t, code: &mut i32) -> bool {
+    let mut status = 0;
+    let res = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+    if res == pid {
+        *code = if libc::WIFEXITED(status) {
+            libc::WEXITSTATUS(status)
+        } else {
+            -libc::WTERMSIG(status)
+        };
+        true
+    } else {
+        false
+    }
+}
+
+fn fork_lock() -> std::sync::MutexGuard<'static, ()> {
+    // A failed scenario must not fail every other one
+    FORK_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn ms(v: u64) -> Duration {
+    Duration::from_millis(v)
+}
+
+type LockNew = unsafe fn(*mut u8, *mut u8) -> Result<(Box<dyn LockImpl>, usize)>;
+type EventNew = unsafe fn(*mut u8, bool) -> Result<(Box<dyn EventImpl>, usize)>;
+type EventOpen = unsafe fn(*mut u8) -> Result<(Box<dyn EventImpl>, usize)>;
+
+struct LockKind {
+    name: &'static str,
+    new: LockNew,
+    open: LockNew,
+    /// `rlock()` can be held by several owners at once
+    shared_reads: bool,
+    /// The OS releases the lock when its owner dies
+    released_on_death: bool,
+    /// The next owner recovers the lock of a dead owner and gets an `AbandonedError`
+    reports_abandoned: bool,
+    /// Panicking while holding the lock poisons it
+    poisons: bool,
+}
+
+fn lock_kind<L: LockInit>(name: &'static str) -> LockKind {
+    LockKind {
+        name,
+        new: L::new,
+        open: L::from_existing,
+        shared_reads: false,
+        released_on_death: false,
+        reports_abandoned: false,
+        poisons: false,
+    }
+}
+
+fn lock_kinds() -> Vec<LockKind> {
+    // Robust mutexes are only available on Linux
+    let robust = cfg!(target_os = "linux");
+    #[allow(unused_mut)]
+    let mut kinds = vec![
+        LockKind {
+            reports_abandoned: robust,
+            ..lock_kind::<Mutex>("Mutex")
+        },
+        LockKind {
+            shared_reads: true,
+            ..lock_kind::<RwLock>("RwLock")
+        },
+        LockKind {
+            poisons: true,
+            reports_abandoned: robust,
+            ..lock_kind::<Poison<Mutex>>("Poison<Mutex>")
+        },
+        LockKind {
+            shared_reads: true,
+            poisons: true,
+            ..lock_kind::<Poison<RwLock>>("Poison<RwLock>")
+        },
+        lock_kind::<NamedSemMutex>("NamedSemMutex"),
+    ];
+    #[cfg(target_os = "linux")]
+    kinds.push(LockKind {
+        shared_reads: true,
+        released_on_death: true,
+        ..lock_kind::<FileLock>("FileLock")
+    });
+    #[cfg(target_os = "linux")]
+    kinds.push(LockKind {
+        released_on_death: true,
+        ..lock_kind::<SysVSemaphore>("SysVSemaphore")
+    });
+    kinds
+}
+
+struct EventKind {
+    name: &'static str,
+    new: EventNew,
+    open: EventOpen,
+}
+
+fn event_kind<E: EventInit>(name: &'static str) -> EventKind {
+    EventKind {
+        name,
+        new: E::new,
+        open: E::from_existing,
+    }
+}
+
+fn event_kinds() -> Vec<EventKind> {
+    vec![
+        event_kind::<Event>("Event"),
+        event_kind::<BusyEvent>("BusyEvent"),
+    ]
+}
+
+#[test]
+fn lock_mutual_exclusion() {
+    const CHILDREN: usize = 4;
+    const ITERATIONS: usize = 200;
+    let _fork = fork_lock();
+
+    for kind in lock_kinds() {
+        let mem = SharedMem::new();
+        let (lock, _) = unsafe { (kind.new)(mem.prim(), mem.data()).unwrap() };
+
+        let children: Vec<_> = (0..CHILDREN)
+            .map(|_| {
+                fork_child(|| {
+                    let (lock, _) = unsafe { (kind.open)(mem.prim(), mem.data()).unwrap() };
+                    for _ in 0..ITERATIONS {
+                        let guard = lock.lock().unwrap();
+                        let counter = unsafe { &mut *(*guard as *mut usize) };
+                        // Non atomic increment, any overlap loses updates
+                        let val = *counter;
+                        std::thread::yield_now();
+                        *counter = val + 1;
+                    }
+                })
+            })
+            .collect();
+
+        for pid in children {
+            assert_eq!(wait_child(pid), 0, "{} : child failed", kind.name);
+        }
+        let guard = lock.rlock().unwrap();
+        let counter = unsafe { *(*guard as *const usize) };
+        assert_eq!(
+            counter,
+            CHILDREN * ITERATIONS,
+            "{} : lost updates",
+            kind.name
+        );
+    }
+}
+
+#[test]
+fn lock_try_lock_times_out() {
+    let _fork = fork_lock();
+
+    for kind in lock_kinds() {
+        let mem = SharedMem::new();
+        let (lock, _) = unsafe { (kind.new)(mem.prim(), mem.data()).unwrap() };
+
+        let child = fork_child(|| {
+            let (lock, _) = unsafe { (kind.open)(mem.prim(), mem.data()).unwrap() };
+            let _guard = lock.lock().unwrap();
+            mem.flag(0).store(1, Ordering::Release);
+            mem.wait_flag(1, 1);
+        });
+        mem.wait_flag(0, 1);
+
+        let start = Instant::now();
+        let err = lock
+            .try_lock(Timeout::Val(ms(100)))
+            .err()
+            .unwrap_or_else(|| panic!("{} : acquired a held lock", kind.name));
+        assert!(err.is::<TimeoutError>(), "{} : {}", kind.name, err);
+        assert!(start.elapsed() >= ms(90), "{} : returned early", kind.name);
+        if !kind.shared_reads {
+            let err = lock.try_rlock(Timeout::Val(ms(50))).err().unwrap();
+            assert!(err.is::<TimeoutError>(), "{} : {}", kind.name, err);
+        }
+
+        mem.flag(1).store(1, Ordering::Release);
+        assert_eq!(wait_child(child), 0, "{} : child failed", kind.name);
+        assert!(
+            lock.try_lock(Timeout::Val(ms(100))).is_ok(),
+            "{} : lock not released",
+            kind.name
+        );
+    }
+}
+
+#[test]
+fn lock_shared_reads() {
+    let _fork = fork_lock();
+
+    for kind in lock_kinds().into_iter().filter(|k| k.shared_reads) {
+        let mem = SharedMem::new();
+        let (lock, _) = unsafe { (kind.new)(mem.prim(), mem.data()).unwrap() };
+
+        let child = fork_child(|| {
+            let (lock, _) = unsafe { (kind.open)(mem.prim(), mem.data()).unwrap() };
+            let _guard = lock.rlock().unwrap();
+            mem.flag(0).store(1, Ordering::Release);
+            mem.wait_flag(1, 1);
+        });
+        mem.wait_flag(0, 1);
+
+        assert!(
+            lock.try_rlock(Timeout::Val(ms(100))).is_ok(),
+            "{} : readers excluded each other",
+            kind.name
+        );
+        assert!(
+            lock.try_lock(Timeout::Val(ms(100))).is_err(),
+            "{} : writer acquired while reader held",
+            kind.name
+        );
+
+        mem.flag(1).store(1, Ordering::Release);
+        assert_eq!(wait_child(child), 0, "{} : child failed", kind.name);
+    }
+}
+
+#[test]
+fn lock_owner_killed_in_critical_section() {
+    let _fork = fork_lock();
+
+    for kind in lock_kinds() {
+        let mem = SharedMem::new();
+        let (lock, _) = unsafe { (kind.new)(mem.prim(), mem.data()).unwrap() };
+
+        let child = fork_child(|| {
+            let (lock, _) = unsafe { (kind.open)(mem.prim(), mem.data()).unwrap() };
+            let _guard = lock.lock().unwrap();
+            mem.flag(0).store(1, Ordering::Release);
+            loop {
+                std::thread::sleep(ms(100));
+            }
+        });
+        mem.wait_flag(0, 1);
+        unsafe { libc::kill(child, libc::SIGKILL) };
+        assert_eq!(wait_child(child), -libc::SIGKILL);
+
+        let res = lock.try_lock(Timeout::Val(ms(100)));
+        if kind.released_on_death {
+            assert!(res.is_ok(), "{} : not released on death", kind.name);
+        } else if kind.reports_abandoned {
+            let err = res.err().expect("acquired a dead owner's lock silently");
+            assert!(err.is::<AbandonedError>(), "{} : {}", kind.name, err);
+            // The lock was recovered and released
+            drop(lock.try_lock(Timeout::Val(ms(100))).unwrap());
+        } else {
+            // The lock is lost for good, waiters must still be able to give up
+            assert!(res.is_err(), "{} : acquired a dead owner's lock", kind.name);
+        }
+    }
+}
+
+#[test]
+fn lock_panic_poisons() {
+    let _fork = fork_lock();
+
+    for kind in lock_kinds().into_iter().filter(|k| k.poisons) {
+        let mem = SharedMem::new();
+        let (lock, _) = unsafe { (kind.new)(mem.prim(), mem.data()).unwrap() };
+
+        let child = fork_child(|| {
+            let (lock, _) = unsafe { (kind.open)(mem.prim(), mem.data()).unwrap() };
+            let _guard = lock.lock().unwrap();
+            panic!("panicking while holding the lock");
+        });
+        assert_eq!(wait_child(child), 101);
+
+        assert!(lock.is_poisoned(), "{}", kind.name);
+        let err = lock.lock().err().expect("poisoned lock was acquired");
+        assert!(err.downcast_ref::<PoisonError>().is_some(), "{}", kind.name);
+        assert!(lock.rlock().is_err(), "{}", kind.name);
+        // The checked variants keep the poisoned lock held and hand its guard back
+        match lock.try_lock_checked(Timeout::Val(ms(100))) {
+            Err(LockError::Poisoned(e)) => {
+                let guard = e.into_inner();
+                assert!(lock.try_lock(Timeout::Val(ms(0))).is_err(), "{}", kind.name);
+                drop(guard);
+            }
+            res => panic!(
+                "{} : expected a poisoned guard, got {:?}",
+                kind.name,
+                res.err()
+            ),
+        }
+        match lock.rlock_checked() {
+            Err(LockError::Poisoned(e)) => drop(e.into_inner()),
+            res => panic!(
+                "{} : expected a poisoned guard, got {:?}",
+                kind.name,
+                res.err()
+            ),
+        }
+        lock.clear_poison();
+        assert!(lock.lock_checked().is_ok(), "{}", kind.name);
+    }
+}
+
+#[test]
+fn registry_detects_cross_process_deadlock() {
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+
+    let (registry, used) = unsafe { LockRegistry::new(mem.prim()).unwrap() };
+    let lock_a = unsafe { mem.prim().add(used) };
+    let (_, used) = unsafe { Mutex::new(lock_a, null_mut()).unwrap() };
+    let lock_b = unsafe { lock_a.add(used) };
+    unsafe { Mutex::new(lock_b, null_mut()).unwrap() };
+
+    // A is held before forking so the child cannot take both locks
+    let a = registry.track(1, unsafe {
+        Mutex::from_existing(lock_a, null_mut()).unwrap().0
+    });
+    let b = registry.track(2, unsafe {
+        Mutex::from_existing(lock_b, null_mut()).unwrap().0
+    });
+    let guard_a = a.lock().unwrap();
+
+    let child = fork_child(|| {
+        let (registry, _) = unsafe { LockRegistry::from_existing(mem.prim()).unwrap() };
+        let a = registry.track(1, unsafe {
+            Mutex::from_existing(lock_a, null_mut()).unwrap().0
+        });
+        let b = registry.track(2, unsafe {
+            Mutex::from_existing(lock_b, null_mut()).unwrap().0
+        });
+        let _b = b.lock().unwrap();
+        mem.flag(0).store(1, Ordering::Release);
+        // Blocks until the parent gives up on B. Either side can be the one to detect
+        // the deadlock, retry until the parent does
+        let _a = loop {
+            match a.lock() {
+                Ok(g) => break g,
+                Err(e) => {
+                    assert!(e.downcast_ref::<DeadlockError>().is_some());
+                    std::thread::sleep(ms(20));
+                }
+            }
+        };
+    });
+
+    mem.wait_flag(0, 1);
+    // Let the child register its wait on A first so this side is the one closing the
+    // cycle, otherwise both sides can keep backing off without either being blocked
+    std::thread::sleep(ms(100));
+
+    let start = Instant::now();
+    let err = loop {
+        match b.try_lock(Timeout::Val(ms(10))) {
+            Err(e) if e.downcast_ref::<DeadlockError>().is_some() => break e,
+            _ => assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "no deadlock found"
+            ),
+        }
+    };
+    let deadlock = &err.downcast_ref::<DeadlockError>().unwrap().deadlock;
+    assert_eq!(deadlock.edges.len(), 2);
+    assert!(deadlock
+        .edges
+        .iter()
+        .any(|e| e.pid == child as u32 && e.lock_id == 1));
+    assert!(deadlock
+        .edges
+        .iter()
+        .any(|e| e.pid == std::process::id() && e.lock_id == 2));
+
+    drop(guard_a);
+    assert_eq!(wait_child(child), 0);
+    assert!(registry.find_deadlocks().unwrap().is_empty());
+
+    // A condvar wait would release the lock behind the registry's back
+    let mut condvar_mem = [0u64; 16];
+    assert!(Condvar::SIZE <= size_of::<[u64; 16]>());
+    let (condvar, _) = unsafe { Condvar::new(condvar_mem.as_mut_ptr() as *mut u8).unwrap() };
+    let guard_a = a.lock().unwrap();
+    assert!(condvar.wait(&guard_a, Timeout::Val(ms(10))).is_err());
+    drop(guard_a);
+    assert!(registry.find_deadlocks().unwrap().is_empty());
+}
+
+/// Lock whose acquires always fail without it being held
+struct BrokenLock {
+    attempts: std::rc::Rc<std::cell::Cell<u32>>,
+    data: std::cell::UnsafeCell<*mut u8>,
+}
+impl LockImpl for BrokenLock {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        null_mut()
+    }
+    fn lock(&self) -> std::result::Result<LockGuard<'_>, Box<dyn std::error::Error>> {
+        self.attempts.set(self.attempts.get() + 1);
+        Err(From::from("broken"))
+    }
+    fn try_lock(
+        &self,
+        _timeout: Timeout,
+    ) -> std::result::Result<LockGuard<'_>, Box<dyn std::error::Error>> {
+        self.lock()
+    }
+    fn release(&self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        &mut *self.data.get()
+    }
+}
+
+/// Lock that is always free but fails to release
+struct UnreleasableLock {
+    data: std::cell::UnsafeCell<*mut u8>,
+}
+impl LockImpl for UnreleasableLock {
+    fn as_raw(&self) -> *mut std::ffi::c_void {
+        null_mut()
+    }
+    fn lock(&self) -> std::result::Result<LockGuard<'_>, Box<dyn std::error::Error>> {
+        Ok(LockGuard::new(self))
+    }
+    fn try_lock(
+        &self,
+        _timeout: Timeout,
+    ) -> std::result::Result<LockGuard<'_>, Box<dyn std::error::Error>> {
+        self.lock()
+    }
+    fn release(&self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        Err(From::from("release failed"))
+    }
+    unsafe fn get_inner(&self) -> &mut *mut u8 {
+        &mut *self.data.get()
+    }
+}
+
+#[test]
+fn release_errors_are_reported() {
+    let lock = UnreleasableLock {
+        data: std::cell::UnsafeCell::new(null_mut()),
+    };
+
+    // Explicit unlocks return the error
+    let err = lock.lock().unwrap().unlock().err().unwrap();
+    assert_eq!(err.to_string(), "release failed");
+    let err = lock.rlock().unwrap().unlock().err().unwrap();
+    assert_eq!(err.to_string(), "release failed");
+    assert!(take_release_error().is_none());
+
+    // Dropping a guard does not panic and records the error for this thread
+    drop(lock.lock().unwrap());
+    let err = take_release_error().unwrap();
+    assert_eq!(err.to_string(), "release failed");
+    assert!(take_release_error().is_none());
+    drop(lock.rlock().unwrap());
+    // Other threads do not see it
+    let other = std::thread::spawn(|| take_release_error().is_some());
+    assert!(!other.join().unwrap());
+    assert!(take_release_error().is_some());
+}
+
+#[test]
+fn registry_only_waits_on_contended_locks() {
+    let mem = SharedMem::new();
+    let (registry, _) = unsafe { LockRegistry::new(mem.prim()).unwrap() };
+    let attempts = std::rc::Rc::new(std::cell::Cell::new(0));
+    let lock = registry.track(
+        1,
+        Box::new(BrokenLock {
+            attempts: attempts.clone(),
+            data: std::cell::UnsafeCell::new(null_mut()),
+        }),
+    );
+
+    // A failure that is not a timeout is returned without blocking on the lock
+    let err = lock.lock().err().unwrap();
+    assert_eq!(err.to_string(), "broken");
+    assert_eq!(attempts.get(), 1);
+    assert!(registry.find_deadlocks().unwrap().is_empty());
+}
+
+#[test]
+fn event_signal_wakes_other_process() {
+    let _fork = fork_lock();
+
+    for kind in event_kinds() {
+        for auto_reset in [true, false] {
+            let mem = SharedMem::new();
+            let (event, _) = unsafe { (kind.new)(mem.prim(), auto_reset).unwrap() };
+
+            let child = fork_child(|| {
+                let (event, _) = unsafe { (kind.open)(mem.prim()).unwrap() };
+                event.wait(Timeout::Val(Duration::from_secs(10))).unwrap();
+                assert_eq!(unsafe { *mem.counter() }, 42);
+            });
+
+            unsafe { *mem.counter() = 42 };
+            event.set(EventState::Signaled).unwrap();
+            assert_eq!(wait_child(child), 0, "{} ({})", kind.name, auto_reset);
+
+            // Only manual reset events are still signaled
+            let res = event.wait(Timeout::Val(ms(50)));
+            assert_eq!(res.is_ok(), !auto_reset, "{} ({})", kind.name, auto_reset);
+        }
+    }
+}
+
+#[test]
+fn event_wait_times_out() {
+    let _fork = fork_lock();
+
+    for kind in event_kinds() {
+        for auto_reset in [true, false] {
+            let mem = SharedMem::new();
+            let _ = unsafe { (kind.new)(mem.prim(), auto_reset).unwrap() };
+
+            let child = fork_child(|| {
+                let (event, _) = unsafe { (kind.open)(mem.prim()).unwrap() };
+                let start = Instant::now();
+                assert!(event.wait(Timeout::Val(ms(100))).is_err());
+                assert!(start.elapsed() >= ms(90));
+            });
+            assert_eq!(wait_child(child), 0, "{} ({})", kind.name, auto_reset);
+        }
+    }
+}
+
+#[test]
+fn event_clear_resets_manual_event() {
+    let _fork = fork_lock();
+
+    for kind in event_kinds() {
+        let mem = SharedMem::new();
+        let (event, _) = unsafe { (kind.new)(mem.prim(), false).unwrap() };
+        event.set(EventState::Signaled).unwrap();
+
+        let child = fork_child(|| {
+            let (event, _) = unsafe { (kind.open)(mem.prim()).unwrap() };
+            event.wait(Timeout::Val(ms(100))).unwrap();
+            event.set(EventState::Clear).unwrap();
+        });
+        assert_eq!(wait_child(child), 0, "{}", kind.name);
+        assert!(event.wait(Timeout::Val(ms(50))).is_err(), "{}", kind.name);
+    }
+}
+
+#[test]
+fn event_many_waiters() {
+    const WAITERS: usize = 4;
+    let _fork = fork_lock();
+
+    for kind in event_kinds() {
+        for auto_reset in [true, false] {
+            let mem = SharedMem::new();
+            let (event, _) = unsafe { (kind.new)(mem.prim(), auto_reset).unwrap() };
+
+            let children: Vec<_> = (0..WAITERS)
+                .map(|_| {
+                    fork_child(|| {
+                        let (event, _) = unsafe { (kind.open)(mem.prim()).unwrap() };
+                        mem.flag(0).fetch_add(1, Ordering::AcqRel);
+                        event.wait(Timeout::Val(Duration::from_secs(10))).unwrap();
+                        mem.flag(1).fetch_add(1, Ordering::AcqRel);
+                    })
+                })
+                .collect();
+            mem.wait_flag(0, WAITERS as u32);
+
+            if !auto_reset {
+                // A single signal wakes every waiter
+                event.set(EventState::Signaled).unwrap();
+            }
+            let mut codes = vec![None; WAITERS];
+            let start = Instant::now();
+            while codes.iter().any(|c| c.is_none()) {
+                assert!(
+                    start.elapsed() < Duration::from_secs(10),
+                    "{} ({}) : waiters never woke",
+                    kind.name,
+                    auto_reset
+                );
+                if auto_reset {
+                    // Signals can collapse, keep signaling until everyone woke up
+                    event.set(EventState::Signaled).unwrap();
+                }
+                std::thread::sleep(ms(5));
+                for (pid, code) in children.iter().zip(codes.iter_mut()) {
+                    let mut c = 0;
+                    if code.is_none() && child_exited(*pid, &mut c) {
+                        *code = Some(c);
+                    }
+                }
+            }
+            assert!(
+                codes.iter().all(|c| *c == Some(0)),
+                "{} ({}) : {:?}",
+                kind.name,
+                auto_reset,
+                codes
+            );
+            assert_eq!(mem.flag(1).load(Ordering::Acquire) as usize, WAITERS);
+        }
+    }
+}
+
+#[test]
+fn spsc_channel_across_processes() {
+    use raw_sync::channel::spsc;
+    const MESSAGES: usize = 500;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+
+    // Small enough for messages to wrap around and for the sender to block
+    let (rx, _) = unsafe { spsc::Receiver::new(mem.prim(), 64).unwrap() };
+    assert!(rx.recv(Timeout::Val(ms(50))).is_err());
+
+    let child = fork_child(|| {
+        let (tx, _) = unsafe { spsc::Sender::from_existing(mem.prim()).unwrap() };
+        for i in 0..MESSAGES {
+            let msg: Vec<u8> = (0..i % 40).map(|b| (b + i) as u8).collect();
+            tx.send(&msg, Timeout::Val(Duration::from_secs(10)))
+                .unwrap();
+        }
+        assert!(tx.send(&[0; 61], Timeout::Infinite).is_err());
+    });
+
+    for i in 0..MESSAGES {
+        let msg = rx.recv(Timeout::Val(Duration::from_secs(10))).unwrap();
+        let expected: Vec<u8> = (0..i % 40).map(|b| (b + i) as u8).collect();
+        assert_eq!(msg, expected);
+    }
+    assert_eq!(wait_child(child), 0);
+    assert!(rx.recv(Timeout::Val(ms(50))).is_err());
+}
+
+#[test]
+fn mpmc_queue_across_processes() {
+    use raw_sync::channel::mpmc::Queue;
+    const PRODUCERS: usize = 3;
+    const CONSUMERS: usize = 2;
+    const ITEMS: usize = 300;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    // A small queue keeps both producers and consumers blocking
+    let (queue, _) = unsafe { Queue::new(mem.prim(), 4, size_of::<u64>()).unwrap() };
+    let mut buf = [0u8; 8];
+    assert!(queue.pop(&mut buf, Timeout::Val(ms(50))).is_err());
+
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|p| {
+            fork_child(move || {
+                let (queue, _) = unsafe { Queue::from_existing(mem.prim()).unwrap() };
+                for i in 0..ITEMS {
+                    let val = (p * ITEMS + i) as u64 + 1;
+                    queue
+                        .push(&val.to_ne_bytes(), Timeout::Val(Duration::from_secs(10)))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    let consumers: Vec<_> = (0..CONSUMERS)
+        .map(|c| {
+            fork_child(move || {
+                let (queue, _) = unsafe { Queue::from_existing(mem.prim()).unwrap() };
+                let mut sum = 0u64;
+                let mut buf = [0u8; 8];
+                for _ in 0..PRODUCERS * ITEMS / CONSUMERS {
+                    let len = queue
+                        .pop(&mut buf, Timeout::Val(Duration::from_secs(10)))
+                        .unwrap();
+                    assert_eq!(len, 8);
+                    sum += u64::from_ne_bytes(buf);
+                }
+                unsafe { *(mem.counter() as *mut u64).add(c) = sum };
+            })
+        })
+        .collect();
+
+    for pid in producers.into_iter().chain(consumers) {
+        assert_eq!(wait_child(pid), 0);
+    }
+    let total: u64 = (0..CONSUMERS)
+        .map(|c| unsafe { *(mem.counter() as *const u64).add(c) })
+        .sum();
+    let n = (PRODUCERS * ITEMS) as u64;
+    assert_eq!(total, n * (n + 1) / 2);
+    assert!(queue.is_empty().unwrap());
+    assert!(queue.push(&[0; 9], Timeout::Infinite).is_err());
+}
+
+#[test]
+fn broadcast_across_processes() {
+    const SUBSCRIBERS: usize = 3;
+    const NOTIFICATIONS: u64 = 50;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    let (broadcast, _) = unsafe { Broadcast::new(mem.prim()).unwrap() };
+    assert_eq!(broadcast.generation().unwrap(), 0);
+    assert!(broadcast.wait_for_change(0, Timeout::Val(ms(50))).is_err());
+
+    let subscribers: Vec<_> = (0..SUBSCRIBERS)
+        .map(|_| {
+            fork_child(move || {
+                let (broadcast, _) = unsafe { Broadcast::from_existing(mem.prim()).unwrap() };
+                mem.flag(0).fetch_add(1, Ordering::AcqRel);
+                let mut last_seen = 0;
+                while last_seen < NOTIFICATIONS {
+                    let generation = broadcast
+                        .wait_for_change(last_seen, Timeout::Val(Duration::from_secs(10)))
+                        .unwrap();
+                    assert!(generation > last_seen);
+                    last_seen = generation;
+                }
+            })
+        })
+        .collect();
+    mem.wait_flag(0, SUBSCRIBERS as u32);
+
+    for i in 1..=NOTIFICATIONS {
+        assert_eq!(broadcast.notify().unwrap(), i);
+        if i % 10 == 0 {
+            std::thread::sleep(ms(5));
+        }
+    }
+    for pid in subscribers {
+        assert_eq!(wait_child(pid), 0);
+    }
+
+    // Late subscribers see the current generation instead of waiting
+    let late = fork_child(|| {
+        let (broadcast, _) = unsafe { Broadcast::from_existing(mem.prim()).unwrap() };
+        let generation = broadcast.wait_for_change(0, Timeout::Val(ms(10))).unwrap();
+        assert_eq!(generation, NOTIFICATIONS);
+    });
+    assert_eq!(wait_child(late), 0);
+}
+
+#[test]
+fn condvar_across_processes() {
+    const WAITERS: usize = 3;
+    const TARGET: usize = 20;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+    let condvar_mem = unsafe { mem.prim().add(1024) };
+
+    let (mutex, _) = unsafe { Mutex::new(mem.prim(), mem.data()).unwrap() };
+    let (condvar, _) = unsafe { Condvar::new(condvar_mem).unwrap() };
+    unsafe { *mem.counter() = 0 };
+
+    {
+        let guard = mutex.lock().unwrap();
+        let err = condvar.wait(&guard, Timeout::Val(ms(50))).err().unwrap();
+        assert!(err.is::<TimeoutError>(), "{}", err);
+        let err = condvar
+            .wait_while(&guard, |_| true, Timeout::Val(ms(50)))
+            .err()
+            .unwrap();
+        assert!(err.is::<TimeoutError>(), "{}", err);
+    }
+
+    let waiters: Vec<_> = (0..WAITERS)
+        .map(|_| {
+            fork_child(move || {
+                let (mutex, _) = unsafe { Mutex::from_existing(mem.prim(), mem.data()).unwrap() };
+                let (condvar, _) = unsafe { Condvar::from_existing(condvar_mem).unwrap() };
+                let guard = mutex.lock().unwrap();
+                mem.flag(0).fetch_add(1, Ordering::AcqRel);
+                condvar
+                    .wait_while(
+                        &guard,
+                        |data| unsafe { *(data as *mut usize) } < TARGET,
+                        Timeout::Val(Duration::from_secs(10)),
+                    )
+                    .unwrap();
+                assert_eq!(unsafe { *(*guard as *mut usize) }, TARGET);
+            })
+        })
+        .collect();
+    mem.wait_flag(0, WAITERS as u32);
+
+    for _ in 0..TARGET {
+        let guard = mutex.lock().unwrap();
+        unsafe { *(*guard as *mut usize) += 1 };
+        drop(guard);
+        condvar.notify_all().unwrap();
+        std::thread::sleep(ms(1));
+    }
+    for pid in waiters {
+        assert_eq!(wait_child(pid), 0);
+    }
+
+    // notify_one wakes a single waiter per call
+    let waiters: Vec<_> = (0..WAITERS)
+        .map(|_| {
+            fork_child(move || {
+                let (mutex, _) = unsafe { Mutex::from_existing(mem.prim(), mem.data()).unwrap() };
+                let (condvar, _) = unsafe { Condvar::from_existing(condvar_mem).unwrap() };
+                let guard = mutex.lock().unwrap();
+                mem.flag(1).fetch_add(1, Ordering::AcqRel);
+                condvar
+                    .wait_while(
+                        &guard,
+                        |data| unsafe { *(data as *mut usize) } == TARGET,
+                        Timeout::Val(Duration::from_secs(10)),
+                    )
+                    .unwrap();
+                unsafe { *(*guard as *mut usize) -= 1 };
+            })
+        })
+        .collect();
+    mem.wait_flag(1, WAITERS as u32);
+
+    let guard = mutex.lock().unwrap();
+    unsafe { *(*guard as *mut usize) += WAITERS };
+    drop(guard);
+    for _ in 0..WAITERS {
+        condvar.notify_one().unwrap();
+    }
+    for pid in waiters {
+        assert_eq!(wait_child(pid), 0);
+    }
+    assert_eq!(unsafe { *mem.counter() }, TARGET);
+}
+
+#[test]
+fn seqlock_readers_see_consistent_snapshots() {
+    const READERS: usize = 3;
+    const WRITES: u64 = 2000;
+    const WORDS: usize = 16;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    let (seqlock, _) = unsafe { SeqLock::new(mem.prim(), mem.data()).unwrap() };
+    let snapshot = |data: *const u8| {
+        let mut words = [0u64; WORDS];
+        for (i, w) in words.iter_mut().enumerate() {
+            *w = unsafe { std::ptr::read_volatile((data as *const u64).add(i)) };
+        }
+        words
+    };
+    assert_eq!(seqlock.read(snapshot), [0; WORDS]);
+
+    // A write in progress blocks readers
+    let guard = seqlock.write().unwrap();
+    assert_eq!(seqlock.sequence() & 1, 1);
+    assert!(seqlock.try_read(Timeout::Val(ms(20)), snapshot).is_err());
+    drop(guard);
+    assert_eq!(seqlock.sequence(), 2);
+
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            fork_child(move || {
+                let (seqlock, _) =
+                    unsafe { SeqLock::from_existing(mem.prim(), mem.data()).unwrap() };
+                mem.flag(0).fetch_add(1, Ordering::AcqRel);
+                let mut last = 0;
+                while last < WRITES {
+                    let words = seqlock.read(snapshot);
+                    assert!(
+                        words.iter().all(|w| *w == words[0]),
+                        "torn read {:?}",
+                        words
+                    );
+                    assert!(words[0] >= last);
+                    last = words[0];
+                }
+            })
+        })
+        .collect();
+    mem.wait_flag(0, READERS as u32);
+
+    let writer = fork_child(move || {
+        let (seqlock, _) = unsafe { SeqLock::from_existing(mem.prim(), mem.data()).unwrap() };
+        for v in 1..=WRITES {
+            let guard = seqlock.write().unwrap();
+            for i in 0..WORDS {
+                unsafe { std::ptr::write_volatile((*guard as *mut u64).add(i), v) };
+            }
+        }
+    });
+    assert_eq!(wait_child(writer), 0);
+    for pid in readers {
+        assert_eq!(wait_child(pid), 0);
+    }
+    assert_eq!(seqlock.sequence(), 2 + 2 * WRITES as u32);
+}
+
+#[test]
+fn once_runs_a_single_initializer() {
+    const RACERS: usize = 4;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    let (once, _) = unsafe { SharedOnce::new(mem.prim()).unwrap() };
+    assert!(once.wait_initialized(Timeout::Val(ms(20))).is_err());
+
+    let racers: Vec<_> = (0..RACERS)
+        .map(|_| {
+            fork_child(move || {
+                let (once, _) = unsafe { SharedOnce::from_existing(mem.prim()).unwrap() };
+                mem.flag(0).fetch_add(1, Ordering::AcqRel);
+                mem.wait_flag(0, RACERS as u32);
+                once.call_once(|| {
+                    mem.flag(1).fetch_add(1, Ordering::AcqRel);
+                    std::thread::sleep(ms(50));
+                });
+                assert!(once.is_completed());
+            })
+        })
+        .collect();
+    once.wait_initialized(Timeout::Val(Duration::from_secs(10)))
+        .unwrap();
+    for pid in racers {
+        assert_eq!(wait_child(pid), 0);
+    }
+    assert_eq!(mem.flag(1).load(Ordering::Acquire), 1);
+
+    once.call_once(|| panic!("initialized twice"));
+}
+
+#[test]
+fn once_recovers_from_dead_initializer() {
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    let (once, _) = unsafe { SharedOnce::new(mem.prim()).unwrap() };
+    let child = fork_child(|| {
+        let (once, _) = unsafe { SharedOnce::from_existing(mem.prim()).unwrap() };
+        once.call_once(|| {
+            mem.flag(0).store(1, Ordering::Release);
+            loop {
+                std::thread::sleep(ms(100));
+            }
+        });
+    });
+    mem.wait_flag(0, 1);
+    assert!(once.wait_initialized(Timeout::Val(ms(20))).is_err());
+    unsafe { libc::kill(child, libc::SIGKILL) };
+    assert_eq!(wait_child(child), -libc::SIGKILL);
+    assert!(!once.is_completed());
+
+    let mut ran = false;
+    once.call_once(|| ran = true);
+    assert!(ran);
+    once.wait_initialized(Timeout::Val(Duration::default()))
+        .unwrap();
+
+    // A panicking initializer lets the next caller retry
+    let (once, _) = unsafe { SharedOnce::new(mem.prim()).unwrap() };
+    assert!(catch_unwind(AssertUnwindSafe(|| once.call_once(|| panic!("init failed")))).is_err());
+    let mut ran = false;
+    once.call_once(|| ran = true);
+    assert!(ran);
+}
+
+#[test]
+fn latch_opens_after_every_worker_reported() {
+    const WORKERS: u32 = 4;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    let (latch, _) = unsafe { Latch::new(mem.prim(), WORKERS).unwrap() };
+    assert_eq!(latch.count(), WORKERS);
+    assert!(latch.wait(Timeout::Val(ms(20))).is_err());
+
+    let waiter = fork_child(move || {
+        let (latch, _) = unsafe { Latch::from_existing(mem.prim()).unwrap() };
+        latch.wait(Timeout::Val(Duration::from_secs(10))).unwrap();
+        assert_eq!(latch.count(), 0);
+        assert_eq!(mem.flag(0).load(Ordering::Acquire), WORKERS);
+    });
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            fork_child(move || {
+                let (latch, _) = unsafe { Latch::from_existing(mem.prim()).unwrap() };
+                std::thread::sleep(ms(10));
+                mem.flag(0).fetch_add(1, Ordering::AcqRel);
+                latch.count_down(1).unwrap();
+            })
+        })
+        .collect();
+
+    latch.wait(Timeout::Val(Duration::from_secs(10))).unwrap();
+    for pid in workers {
+        assert_eq!(wait_child(pid), 0);
+    }
+    assert_eq!(wait_child(waiter), 0);
+
+    // The latch stays open and extra count downs are ignored
+    assert_eq!(latch.count_down(3).unwrap(), 0);
+    latch.wait(Timeout::Val(Duration::default())).unwrap();
+
+    let (latch, _) = unsafe { Latch::new(mem.prim(), 5).unwrap() };
+    assert_eq!(latch.count_down(2).unwrap(), 3);
+    assert_eq!(latch.count_down(10).unwrap(), 0);
+    latch.wait(Timeout::Val(Duration::default())).unwrap();
+}
+
+#[test]
+fn event_group_wait_any_and_all() {
+    const READY: u32 = 1 << 0;
+    const CONFIGURED: u32 = 1 << 1;
+    const JOB: u32 = 1 << 2;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    let (group, _) = unsafe { EventGroup::new(mem.prim()).unwrap() };
+    assert_eq!(group.bits().unwrap(), 0);
+    assert!(group
+        .wait_bits(READY, WaitMode::Any, false, Timeout::Val(ms(20)))
+        .is_err());
+
+    let all_waiter = fork_child(move || {
+        let (group, _) = unsafe { EventGroup::from_existing(mem.prim()).unwrap() };
+        mem.flag(0).fetch_add(1, Ordering::AcqRel);
+        let bits = group
+            .wait_bits(
+                READY | CONFIGURED,
+                WaitMode::All,
+                false,
+                Timeout::Val(Duration::from_secs(10)),
+            )
+            .unwrap();
+        assert_eq!(bits & (READY | CONFIGURED), READY | CONFIGURED);
+    });
+    let any_waiter = fork_child(move || {
+        let (group, _) = unsafe { EventGroup::from_existing(mem.prim()).unwrap() };
+        mem.flag(0).fetch_add(1, Ordering::AcqRel);
+        let bits = group
+            .wait_bits(
+                CONFIGURED | JOB,
+                WaitMode::Any,
+                false,
+                Timeout::Val(Duration::from_secs(10)),
+            )
+            .unwrap();
+        assert_ne!(bits & (CONFIGURED | JOB), 0);
+    });
+    mem.wait_flag(0, 2);
+
+    assert_eq!(group.set_bits(READY).unwrap(), READY);
+    std::thread::sleep(ms(20));
+    // Neither waiter is satisfied by READY alone
+    let mut code = 0;
+    assert!(!child_exited(all_waiter, &mut code));
+    assert!(!child_exited(any_waiter, &mut code));
+
+    assert_eq!(group.set_bits(CONFIGURED).unwrap(), READY | CONFIGURED);
+    assert_eq!(wait_child(all_waiter), 0);
+    assert_eq!(wait_child(any_waiter), 0);
+
+    // Consuming waiters clear the bits they waited for
+    let consumer = fork_child(move || {
+        let (group, _) = unsafe { EventGroup::from_existing(mem.prim()).unwrap() };
+        for _ in 0..2 {
+            group
+                .wait_bits(
+                    JOB,
+                    WaitMode::Any,
+                    true,
+                    Timeout::Val(Duration::from_secs(10)),
+                )
+                .unwrap();
+            mem.flag(1).fetch_add(1, Ordering::AcqRel);
+        }
+    });
+    group.set_bits(JOB).unwrap();
+    mem.wait_flag(1, 1);
+    assert_eq!(group.bits().unwrap() & JOB, 0);
+    group.set_bits(JOB).unwrap();
+    assert_eq!(wait_child(consumer), 0);
+    assert_eq!(group.clear_bits(READY).unwrap(), CONFIGURED);
+}
+
+#[test]
+fn counting_event_wakes_exactly_n_waiters() {
+    const WAITERS: usize = 5;
+    const SIGNALS: u32 = 3;
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let mem = &mem;
+
+    let (event, _) = unsafe { CountingEvent::new(mem.prim()).unwrap() };
+    // Signals sent before anyone waits are kept instead of collapsing
+    event.set(EventState::Signaled).unwrap();
+    event.set(EventState::Signaled).unwrap();
+    assert_eq!(event.pending().unwrap(), 2);
+    event.wait(Timeout::Val(Duration::default())).unwrap();
+    event.wait(Timeout::Val(Duration::default())).unwrap();
+    assert!(event.wait(Timeout::Val(ms(20))).is_err());
+
+    let children: Vec<_> = (0..WAITERS)
+        .map(|_| {
+            fork_child(move || {
+                let (event, _) = unsafe { CountingEvent::from_existing(mem.prim()).unwrap() };
+                mem.flag(0).fetch_add(1, Ordering::AcqRel);
+                if event.wait(Timeout::Val(ms(500))).is_ok() {
+                    mem.flag(1).fetch_add(1, Ordering::AcqRel);
+                }
+            })
+        })
+        .collect();
+    mem.wait_flag(0, WAITERS as u32);
+    std::thread::sleep(ms(20));
+
+    event.signal_n(SIGNALS).unwrap();
+    for pid in children {
+        assert_eq!(wait_child(pid), 0);
+    }
+    assert_eq!(mem.flag(1).load(Ordering::Acquire), SIGNALS);
+    assert_eq!(event.pending().unwrap(), 0);
+
+    event.signal_n(4).unwrap();
+    event.set(EventState::Clear).unwrap();
+    assert_eq!(event.pending().unwrap(), 0);
+}
+
+#[test]
+fn named_primitives_across_processes() {
+    const CHILDREN: usize = 3;
+    const ITERATIONS: usize = 100;
+    let _fork = fork_lock();
+    let lock_name = format!("raw_sync_lock_{}", std::process::id());
+    let event_name = format!("raw_sync_event_{}", std::process::id());
+    let (lock_name, event_name) = (lock_name.as_str(), event_name.as_str());
+
+    let lock = Mutex::create_named(lock_name, size_of::<usize>()).unwrap();
+    assert!(lock.is_owner());
+    assert_eq!(lock.name(), lock_name);
+    assert!(Mutex::create_named(lock_name, size_of::<usize>()).is_err());
+    let event = Event::create_named(event_name, false).unwrap();
+
+    let children: Vec<_> = (0..CHILDREN)
+        .map(|_| {
+            fork_child(|| {
+                let lock = Mutex::open_named(lock_name).unwrap();
+                let event = Event::open_named(event_name).unwrap();
+                assert!(!lock.is_owner());
+                event.wait(Timeout::Val(Duration::from_secs(10))).unwrap();
+                for _ in 0..ITERATIONS {
+                    let guard = lock.lock().unwrap();
+                    unsafe { *(*guard as *mut usize) += 1 };
+                }
+            })
+        })
+        .collect();
+    event.set(EventState::Signaled).unwrap();
+    for pid in children {
+        assert_eq!(wait_child(pid), 0);
+    }
+    let guard = lock.lock().unwrap();
+    assert_eq!(unsafe { *(*guard as *mut usize) }, CHILDREN * ITERATIONS);
+    drop(guard);
+
+    // Dropping the owner removes the names
+    drop(lock);
+    drop(event);
+    assert!(Mutex::open_named(lock_name).is_err());
+    assert!(Event::open_named(event_name).is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn file_lock_ranges_are_independent() {
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let path = std::env::temp_dir().join(format!("raw_sync_ranges_{}.lock", std::process::id()));
+    let path = path.as_path();
+
+    let first = FileLock::open(path, 0..8, null_mut()).unwrap();
+    let _guard = first.lock().unwrap();
+
+    let child = fork_child(|| {
+        // Same range as the parent
+        let same = FileLock::open(path, 0..8, null_mut()).unwrap();
+        assert!(same.try_lock(Timeout::Val(ms(20))).is_err());
+        assert!(same.try_rlock(Timeout::Val(ms(20))).is_err());
+        // Overlapping range
+        let overlap = FileLock::open(path, 4..12, null_mut()).unwrap();
+        assert!(overlap.try_lock(Timeout::Val(ms(20))).is_err());
+        // Disjoint range
+        let other = FileLock::open(path, 8..16, null_mut()).unwrap();
+        let _g = other.try_lock(Timeout::Val(ms(20))).unwrap();
+        mem.flag(0).store(1, Ordering::Release);
+        mem.wait_flag(1, 1);
+    });
+    mem.wait_flag(0, 1);
+    // The child holds 8..16 while the parent holds 0..8
+    let other = FileLock::open(path, 8..16, null_mut()).unwrap();
+    assert!(other.try_lock(Timeout::Val(ms(20))).is_err());
+    mem.flag(1).store(1, Ordering::Release);
+    assert_eq!(wait_child(child), 0);
+    other.try_lock(Timeout::Val(ms(20))).unwrap();
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn file_lock_range_is_shared() {
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+
+    let (lock, _) = unsafe { FileLock::with_range(mem.prim(), 0..8, null_mut()).unwrap() };
+    let (other, _) = unsafe { FileLock::with_range(mem.data(), 8..16, null_mut()).unwrap() };
+    let _guard = lock.lock().unwrap();
+
+    let child = fork_child(|| {
+        let (lock, _) = unsafe { FileLock::from_existing(mem.prim(), null_mut()).unwrap() };
+        assert!(lock.try_lock(Timeout::Val(ms(20))).is_err());
+        let (other, _) = unsafe { FileLock::attach(mem.data(), null_mut()).unwrap() };
+        assert_eq!(other.range(), 8..16);
+        let _g = other.try_lock(Timeout::Val(ms(20))).unwrap();
+    });
+    assert_eq!(wait_child(child), 0);
+    drop(other);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn sysv_semaphore_returns_permits_of_dead_process() {
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let (sem, _) = unsafe { SysVSemaphore::with_permits(mem.prim(), 3, null_mut()).unwrap() };
+
+    let child = fork_child(|| {
+        let (sem, _) = unsafe { SysVSemaphore::attach(mem.prim(), null_mut()).unwrap() };
+        sem.acquire(2, Timeout::Infinite).unwrap();
+        mem.flag(0).store(1, Ordering::Release);
+        loop {
+            std::thread::sleep(ms(100));
+        }
+    });
+    mem.wait_flag(0, 1);
+    assert_eq!(sem.permits().unwrap(), 1);
+    assert!(sem.acquire(2, Timeout::Val(ms(20))).is_err());
+
+    unsafe { libc::kill(child, libc::SIGKILL) };
+    assert_eq!(wait_child(child), -libc::SIGKILL);
+    assert_eq!(sem.permits().unwrap(), 3);
+    sem.acquire(3, Timeout::Val(ms(20))).unwrap();
+    sem.release_permits(3).unwrap();
+}
+
+#[test]
+fn region_across_processes() {
+    use raw_sync::region::{Entry, Kind, RegionBuilder};
+
+    let build = || {
+        RegionBuilder::new()
+            .entry(Kind::Mutex)
+            .entry(Kind::Data {
+                size: size_of::<usize>(),
+                align: 8,
+            })
+            .entry(Kind::Event { auto_reset: false })
+            .entry(Kind::Latch { count: 2 })
+            .build()
+            .unwrap()
+    };
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let layout = build();
+    assert!(layout.size() <= DATA_OFFSET);
+    let (region, used) = unsafe { layout.create(mem.prim()).unwrap() };
+    assert_eq!(used, layout.size());
+
+    let children: Vec<_> = (0..2)
+        .map(|_| {
+            fork_child(|| {
+                // Every process builds the same layout on its own
+                let (region, _) = unsafe { build().open(mem.prim()).unwrap() };
+                // The mutex protects the data entry following it
+                let guard = region.lock(0).unwrap().lock().unwrap();
+                assert_eq!(*guard, region.data(1).unwrap());
+                unsafe { *(*guard as *mut usize) += 1 };
+                drop(guard);
+                match region.get(3) {
+                    Some(Entry::Latch(latch)) => {
+                        latch.count_down(1).unwrap();
+                    }
+                    _ => panic!("entry 3 is not a latch"),
+                }
+                region.event(2).unwrap().wait(Timeout::Infinite).unwrap();
+            })
+        })
+        .collect();
+
+    match region.get(3) {
+        Some(Entry::Latch(latch)) => latch.wait(Timeout::Val(Duration::from_secs(10))).unwrap(),
+        _ => panic!("entry 3 is not a latch"),
+    }
+    assert_eq!(unsafe { *(region.data(1).unwrap() as *const usize) }, 2);
+    region.event(2).unwrap().set(EventState::Signaled).unwrap();
+    for child in children {
+        assert_eq!(wait_child(child), 0);
+    }
+
+    // A process expecting another layout is refused
+    let other = RegionBuilder::new()
+        .entry(Kind::Data {
+            size: size_of::<usize>(),
+            align: 8,
+        })
+        .entry(Kind::Mutex)
+        .build()
+        .unwrap();
+    let err = unsafe { other.open(mem.prim()) }.err().unwrap();
+    assert!(err.to_string().contains("does not match"), "{}", err);
+    assert!(region.lock(1).is_none());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn seqlock_recovers_from_dead_writer() {
+    let _fork = fork_lock();
+    let mem = SharedMem::new();
+    let (seqlock, _) = unsafe { SeqLock::new(mem.prim(), mem.data()).unwrap() };
+
+    let writer = fork_child(|| {
+        let (seqlock, _) = unsafe { SeqLock::from_existing(mem.prim(), mem.data()).unwrap() };
+        let _guard = seqlock.write().unwrap();
+        mem.flag(0).store(1, Ordering::Release);
+        loop {
+            std::thread::sleep(ms(100));
+        }
+    });
+    mem.wait_flag(0, 1);
+    unsafe { libc::kill(writer, libc::SIGKILL) };
+    assert_eq!(wait_child(writer), -libc::SIGKILL);
+
+    // The half written data is never handed to readers
+    assert_eq!(seqlock.sequence(), 1);
+    assert!(seqlock.try_read(Timeout::Val(ms(20)), |_| ()).is_err());
+    let err = seqlock.write().err().unwrap();
+    assert!(err.is::<AbandonedError>(), "{}", err);
+    assert_eq!(seqlock.sequence(), 1);
+
+    // The next write completes the sequence
+    drop(seqlock.write().unwrap());
+    assert_eq!(seqlock.sequence(), 2);
+    seqlock.try_read(Timeout::Val(ms(20)), |_| ()).unwrap();
+}